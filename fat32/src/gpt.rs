@@ -0,0 +1,258 @@
+use std::{fmt, io, mem, ptr};
+
+use traits::BlockDevice;
+
+/// A globally unique identifier as stored on disk: the first three fields are
+/// little-endian, the last two are big-endian.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    /// The type of an unused partition entry.
+    pub const UNUSED: Guid = Guid([0; 16]);
+
+    /// `EBD0A0A2-B9E5-4433-87C0-68B6B72699C7`: a Microsoft basic data
+    /// partition, the usual type of a FAT volume.
+    pub const BASIC_DATA: Guid = Guid([
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44,
+        0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+    ]);
+
+    /// `C12A7328-F81F-11D2-BA4B-00A0C93EC93B`: an EFI system partition, which
+    /// is formatted as FAT.
+    pub const EFI_SYSTEM: Guid = Guid([
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11,
+        0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+    ]);
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0;
+        write!(f, "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-",
+               b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9])?;
+        for byte in b[10..].iter() {
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guid({})", self)
+    }
+}
+
+/// The GPT header, found in sector 1 of the device.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptHeader {
+    signature: [u8; 8],
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    _reserved: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc32: u32,
+}
+
+/// An entry in the GPT partition entry array. Entries whose type is
+/// `Guid::UNUSED` do not describe a partition.
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+pub struct GptEntry {
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    name: [u16; 36],
+}
+
+/// A partition in a GUID partition table (GPT).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PartitionId {
+    /// The partition described by entry `.0` (0-indexed) of the entry array.
+    Index(usize),
+    /// The first partition whose type is `.0`.
+    Type(Guid),
+}
+
+/// A GUID partition table (GPT): its header and its partition entry array.
+#[derive(Debug)]
+pub struct GuidPartitionTable {
+    pub header: GptHeader,
+    /// Every entry in the array, used or not, in order.
+    pub entries: Vec<GptEntry>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// There was an I/O error while reading the GPT.
+    Io(io::Error),
+    /// The GPT header's magic signature was invalid.
+    BadSignature,
+    /// The GPT header's size or partition entry size is invalid.
+    BadHeader,
+    /// The CRC32 of the GPT header did not match the header's checksum.
+    BadHeaderChecksum,
+    /// The CRC32 of the partition entry array did not match the header's
+    /// checksum.
+    BadEntriesChecksum,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// The size, in bytes, of the fields of the GPT header covered by its CRC32.
+const HEADER_SIZE: usize = 92;
+
+/// The size, in bytes, of the fields of a partition entry.
+const ENTRY_SIZE: usize = 128;
+
+/// Entry arrays larger than this, in bytes, are rejected as invalid.
+const MAX_ENTRIES_SIZE: usize = 1 << 20;
+
+/// Reads `count` sectors starting at `start` from `device` into a new vector.
+fn read_sectors<T: BlockDevice>(device: &mut T, start: u64, count: u64) -> io::Result<Vec<u8>> {
+    let len = (count * device.sector_size()) as usize;
+    let mut buf = vec![0; len];
+    if device.read_sectors(start, count, &mut buf)? < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short sector read"));
+    }
+    Ok(buf)
+}
+
+impl GuidPartitionTable {
+    /// Reads and returns the primary GUID partition table from `device`.
+    ///
+    /// The protective MBR in sector 0 is not examined. The backup table at the
+    /// end of the device is not consulted.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadSignature` if the header contains an invalid magic
+    /// signature. Returns `BadHeader` if the header or entry size is invalid.
+    /// Returns `BadHeaderChecksum` or `BadEntriesChecksum` if the CRC32 of the
+    /// header or of the partition entry array is incorrect. Returns `Io(err)`
+    /// if the I/O error `err` occured while reading the GPT.
+    pub fn from<T: BlockDevice>(mut device: T) -> Result<GuidPartitionTable, Error> {
+        assert_eq!(mem::size_of::<GptHeader>(), HEADER_SIZE);
+        assert_eq!(mem::size_of::<GptEntry>(), ENTRY_SIZE);
+
+        let sector_size = device.sector_size();
+        let mut sector = read_sectors(&mut device, 1, 1)?;
+        let header: GptHeader = unsafe { ptr::read(sector.as_ptr() as *const GptHeader) };
+        if &header.signature != b"EFI PART" {
+            return Err(Error::BadSignature);
+        }
+
+        let header_size = header.header_size as usize;
+        let entry_size = header.entry_size as usize;
+        if header_size < HEADER_SIZE || header_size > sector.len() {
+            return Err(Error::BadHeader);
+        } else if entry_size < ENTRY_SIZE || entry_size % 8 != 0 {
+            return Err(Error::BadHeader);
+        }
+
+        // the checksum is computed with the checksum field zeroed
+        for byte in sector[16..20].iter_mut() {
+            *byte = 0;
+        }
+        if crc32(&sector[..header_size]) != header.header_crc32 {
+            return Err(Error::BadHeaderChecksum);
+        }
+
+        let entries_size = match (header.entry_count as usize).checked_mul(entry_size) {
+            Some(size) if size <= MAX_ENTRIES_SIZE => size,
+            _ => return Err(Error::BadHeader),
+        };
+        let entry_sectors = (entries_size as u64 + sector_size - 1) / sector_size;
+        let data = read_sectors(&mut device, header.entries_lba, entry_sectors)?;
+        if crc32(&data[..entries_size]) != header.entries_crc32 {
+            return Err(Error::BadEntriesChecksum);
+        }
+
+        let entries = data[..entries_size]
+            .chunks(entry_size)
+            .map(|entry| unsafe { ptr::read(entry.as_ptr() as *const GptEntry) })
+            .collect();
+
+        Ok(GuidPartitionTable { header, entries })
+    }
+
+    /// Returns the used entry identified by `id`, if there is one.
+    pub fn find(&self, id: PartitionId) -> Option<&GptEntry> {
+        match id {
+            PartitionId::Index(i) => match self.entries.get(i) {
+                Some(entry) if entry.is_used() => Some(entry),
+                _ => None,
+            },
+            PartitionId::Type(guid) => self.entries.iter().find(|entry| entry.type_guid == guid),
+        }
+    }
+}
+
+impl GptEntry {
+    /// Returns `true` if this entry describes a partition.
+    pub fn is_used(&self) -> bool {
+        self.type_guid != Guid::UNUSED
+    }
+
+    /// The partition's name.
+    pub fn name(&self) -> String {
+        let name = self.name;
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        String::from_utf16_lossy(&name[..len])
+    }
+}
+
+impl fmt::Debug for GptHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptHeader")
+            .field("revision", &{ self.revision })
+            .field("current_lba", &{ self.current_lba })
+            .field("backup_lba", &{ self.backup_lba })
+            .field("disk_guid", &self.disk_guid)
+            .field("entries_lba", &{ self.entries_lba })
+            .field("entry_count", &{ self.entry_count })
+            .field("entry_size", &{ self.entry_size })
+            .finish()
+    }
+}
+
+impl fmt::Debug for GptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GptEntry")
+            .field("type_guid", &self.type_guid)
+            .field("unique_guid", &self.unique_guid)
+            .field("first_lba", &{ self.first_lba })
+            .field("last_lba", &{ self.last_lba })
+            .field("name", &self.name())
+            .finish()
+    }
+}
+
+/// Returns the CRC32 (IEEE 802.3) checksum of `data`, as used by the GPT.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -9,6 +9,7 @@ mod tests;
 mod mbr;
 mod util;
 
+pub mod gpt;
 pub mod vfat;
 pub mod traits;
 
@@ -19,6 +19,34 @@ pub struct PartitionEntry {
     pub total_sectors: u32,
 }
 
+impl PartitionEntry {
+    /// Returns `true` if this entry does not describe a partition.
+    pub fn is_empty(&self) -> bool {
+        self.partition_type == 0
+    }
+
+    /// Returns `true` if this entry's type is one of the FAT32 partition
+    /// types: 0x0B (CHS addressed) or 0x0C (LBA addressed).
+    pub fn is_fat32(&self) -> bool {
+        self.partition_type == 0x0B || self.partition_type == 0x0C
+    }
+
+    /// Returns `true` if this entry's type is one of the FAT partition types:
+    /// 0x01 (FAT12), 0x04, 0x06 and 0x0E (FAT16), or a FAT32 type.
+    pub fn is_fat(&self) -> bool {
+        match self.partition_type {
+            0x01 | 0x04 | 0x06 | 0x0E => true,
+            _ => self.is_fat32(),
+        }
+    }
+
+    /// Returns `true` if this entry describes an extended partition: type
+    /// 0x05 (CHS addressed) or 0x0F (LBA addressed).
+    pub fn is_extended(&self) -> bool {
+        self.partition_type == 0x05 || self.partition_type == 0x0F
+    }
+}
+
 /// The master boot record (MBR).
 #[repr(C, packed)]
 pub struct MasterBootRecord {
@@ -36,8 +64,13 @@ pub enum Error {
     UnknownBootIndicator(u8),
     /// The MBR magic signature was invalid.
     BadSignature,
+    /// The chain of extended boot records loops or is implausibly long.
+    BadExtendedChain,
 }
 
+/// Extended partitions with more logical partitions than this are rejected.
+const MAX_LOGICAL_PARTITIONS: usize = 128;
+
 impl MasterBootRecord {
     /// Reads and returns the master boot record (MBR) from `device`.
     ///
@@ -47,7 +80,13 @@ impl MasterBootRecord {
     /// Returns `UnknownBootIndicator(n)` if partition `n` contains an invalid
     /// boot indicator. Returns `Io(err)` if the I/O error `err` occured while
     /// reading the MBR.
-    pub fn from<T: BlockDevice>(mut device: T) -> Result<MasterBootRecord, Error> {
+    pub fn from<T: BlockDevice>(device: T) -> Result<MasterBootRecord, Error> {
+        MasterBootRecord::read(device, 0)
+    }
+
+    /// Reads the master boot record or extended boot record in sector
+    /// `sector` of `device`.
+    fn read<T: BlockDevice>(mut device: T, sector: u64) -> Result<MasterBootRecord, Error> {
         assert_eq!(mem::size_of::<MasterBootRecord>(), 512);
         let mut mbr: MasterBootRecord = unsafe { mem::uninitialized() };
         {
@@ -56,7 +95,7 @@ impl MasterBootRecord {
                     &mut mbr as *mut MasterBootRecord as *mut u8, 
                     512)
             };
-            if let Err(x) = device.read_sector(0, &mut mbr_as_buf) {
+            if let Err(x) = device.read_sector(sector, &mut mbr_as_buf) {
                 return Err(Error::Io(x));
             }
         } 
@@ -72,6 +111,56 @@ impl MasterBootRecord {
             Err(Error::BadSignature)
         }
     }
+
+    /// Reads the logical partitions of every extended partition in this MBR
+    /// from `device` by following each extended partition's chain of extended
+    /// boot records (EBRs). The partitions are returned in chain order with
+    /// absolute start sectors.
+    ///
+    /// # Errors
+    ///
+    /// Returns `BadExtendedChain` if a chain of EBRs loops. Otherwise returns
+    /// the errors of `from()` for each EBR.
+    pub fn logical_partitions<T: BlockDevice>(
+        &self,
+        mut device: T
+    ) -> Result<Vec<PartitionEntry>, Error> {
+        let mut logical = Vec::new();
+        for extended in self.part_entries.iter().filter(|entry| entry.is_extended()) {
+            // the first EBR is at the start of the extended partition; links
+            // to the next EBR are relative to that start while logical
+            // partitions are relative to their own EBR
+            let base = extended.start_sector;
+            let mut ebr_sector = base;
+            let mut visited = Vec::new();
+            loop {
+                if visited.contains(&ebr_sector) || visited.len() >= MAX_LOGICAL_PARTITIONS {
+                    return Err(Error::BadExtendedChain);
+                }
+                visited.push(ebr_sector);
+
+                let ebr = MasterBootRecord::read(&mut device, ebr_sector as u64)?;
+                if !ebr.part_entries[0].is_empty() {
+                    let mut entry = ebr.part_entries[0].clone();
+                    entry.start_sector = match ebr_sector.checked_add(entry.start_sector) {
+                        Some(start) => start,
+                        None => return Err(Error::BadExtendedChain),
+                    };
+                    logical.push(entry);
+                }
+
+                let next = &ebr.part_entries[1];
+                if !next.is_extended() {
+                    break;
+                }
+                ebr_sector = match base.checked_add(next.start_sector) {
+                    Some(sector) => sector,
+                    None => return Err(Error::BadExtendedChain),
+                };
+            }
+        }
+        Ok(logical)
+    }
 }
 
 impl fmt::Debug for MasterBootRecord {
@@ -3,9 +3,11 @@ extern crate rand;
 use std::io::prelude::*;
 use std::io::Cursor;
 use std::path::Path;
+use std::cmp::min;
 
-use vfat::{Shared, VFat, BiosParameterBlock};
+use vfat::{Shared, VFat, BiosParameterBlock, MountOptions, WritePolicy, Stats, CachedDevice, Partition, Status};
 use mbr::{MasterBootRecord, CHS, PartitionEntry};
+use gpt::{GuidPartitionTable, Guid, PartitionId};
 use traits::*;
 
 macro check_size($T:ty, $size:expr) {
@@ -57,6 +59,16 @@ macro vfat_from_resource($name:expr) {
     VFat::from(resource!($name)).expect("failed to initialize VFAT from image")
 }
 
+macro resource_data($name:expr) {{
+    let mut data = Vec::new();
+    resource!($name).read_to_end(&mut data).expect("read resource data");
+    data
+}}
+
+macro vfat_from_resource_in_memory($name:expr) {
+    VFat::from(Cursor::new(resource_data!($name))).expect("failed to initialize VFAT from image")
+}
+
 #[test]
 fn check_mbr_size() {
     check_size!(MasterBootRecord, 512);
@@ -372,6 +384,1241 @@ fn test_mock4_files_recursive() {
     assert_hash_eq!("mock 4 file hashes", hash, hash_for!("files-2-3-4"));
 }
 
+fn read_file<P: AsRef<Path>>(vfat: &Shared<VFat>, path: P) -> Vec<u8> {
+    let mut data = Vec::new();
+    vfat.open_file(path).expect("file exists")
+        .read_to_end(&mut data)
+        .expect("read file");
+    data
+}
+
+fn first_file_in_root(vfat: &Shared<VFat>) -> String {
+    vfat.open_dir("/").expect("root directory")
+        .entries().expect("entries interator")
+        .find(|e| e.is_file() && e.as_file().unwrap().size() > 0)
+        .expect("a non-empty file in the root directory")
+        .name().to_string()
+}
+
+#[test]
+fn test_write_overwrite_and_extend() {
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let path = Path::new("/").join(first_file_in_root(&vfat));
+    let original = read_file(&vfat, &path);
+
+    let cluster_size = vfat.borrow().cluster_size();
+    let appended: Vec<u8> = (0..(cluster_size * 3 + 17)).map(|i| (i % 251) as u8).collect();
+    {
+        let mut file = vfat.open_file(&path).expect("file exists");
+        file.write_all(b"!").expect("overwrite first byte");
+        let mut rest = vec![0; original.len() - 1];
+        file.read_exact(&mut rest).expect("read rest of file");
+        file.write_all(&appended).expect("extend file");
+        assert_eq!(file.size(), (original.len() + appended.len()) as u64);
+    }
+
+    let data = read_file(&vfat, &path);
+    assert_eq!(data.len(), original.len() + appended.len());
+    assert_eq!(data[0], b'!');
+    assert_eq!(&data[1..original.len()], &original[1..]);
+    assert_eq!(&data[original.len()..], &appended[..]);
+}
+
+#[test]
+fn test_write_until_full() {
+    let image = SharedImage::new(fat16_volume(2880, 9, 64, true));
+    let vfat = VFat::from(image.clone()).expect("mount volume");
+    let cluster_size = vfat.borrow().cluster_size();
+    let capacity = vfat.borrow_mut().free_clusters().unwrap() as usize * cluster_size;
+
+    let mut file = vfat.create_file("/full.bin").expect("create file");
+    let data = vec![0xC3; capacity + 1000];
+    assert_eq!(file.write(&data).expect("partial write"), capacity);
+    assert_eq!(file.size(), capacity as u64);
+    let e = file.write(&data[capacity..]).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+
+    drop(file);
+    drop(vfat);
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount volume");
+    assert!(read_file(&remounted, "/full.bin") == &data[..capacity], "written bytes are recorded");
+}
+
+#[test]
+fn test_create_file() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    for i in 0..64 {
+        let path = format!("/A Rather Long File Name, Number {}.txt", i);
+        let mut file = vfat.create_file(&path).expect("create file");
+        assert_eq!(file.size(), 0);
+        file.write_all(path.as_bytes()).expect("write new file");
+    }
+
+    for i in 0..64 {
+        let path = format!("/A Rather Long File Name, Number {}.txt", i);
+        assert_eq!(read_file(&vfat, &path), path.as_bytes());
+        let entry = vfat.open(path.to_lowercase()).expect("case-insensitive open");
+        assert_eq!(entry.name(), &path[1..]);
+    }
+
+    // every name maps to the base `ARATHE`; each must get its own short name
+    let mut buf = Vec::new();
+    let root = vfat.borrow().root_cluster();
+    vfat.borrow().read_dir(root, &mut buf).expect("read root directory");
+    let mut short_names: Vec<&[u8]> = buf.chunks(32)
+        .take_while(|slot| slot[0] != 0)
+        .filter(|slot| slot[0] != 0xE5 && slot[11] != 0x0F)
+        .map(|slot| &slot[..11])
+        .collect();
+    let count = short_names.len();
+    short_names.sort();
+    short_names.dedup();
+    assert_eq!(short_names.len(), count, "short names are unique");
+
+    let e = vfat.create_file("/a rather long file name, number 0.TXT").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.create_file("relative.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.create_file("/does not exist/file.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = vfat.create_file("/bad?name").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_create_dir() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let e = vfat.create_dir("/new/nested/dir", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    vfat.create_dir("/new/nested/dir", true).expect("create directories");
+    let e = vfat.create_dir("/new/nested", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+
+    let mut names: Vec<_> = vfat.open_dir("/new/nested/dir").expect("new directory")
+        .entries().expect("entries interator")
+        .map(|e| e.name().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec![".", ".."]);
+
+    vfat.create_file("/new/nested/dir/file").expect("create file")
+        .write_all(b"contents").expect("write file");
+    assert_eq!(read_file(&vfat, "/new/nested/dir/file"), b"contents");
+
+    let parent = vfat.open_dir("/new").expect("new directory")
+        .find("..").expect("'..' entry")
+        .into_dir().expect("'..' is a directory");
+    assert!(parent.find("new").is_ok(), "'..' of '/new' is the root directory");
+}
+
+#[test]
+fn test_remove() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    vfat.create_dir("/tree/a/b", true).expect("create directories");
+    for i in 0..8 {
+        vfat.create_file(format!("/tree/a/file {}", i)).expect("create file")
+            .write_all(&[i as u8; 5000]).expect("write file");
+    }
+
+    vfat.remove("/tree/a/file 0", false).expect("remove file");
+    let e = vfat.open("/tree/a/file 0").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = vfat.remove("/tree/a/file 0", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    vfat.remove("/tree/a/b", false).expect("remove empty directory");
+    let e = vfat.remove("/tree", false).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Other);
+
+    vfat.remove("/tree", true).expect("remove directory tree");
+    let e = vfat.open("/tree").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    vfat.create_file("/tree").expect("reuse name")
+        .write_all(b"reused").expect("write file");
+    assert_eq!(read_file(&vfat, "/tree"), b"reused");
+}
+
+#[test]
+fn test_removed_file_handle() {
+    use std::io::ErrorKind;
+
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let vfat = VFat::from(image.clone()).expect("mount image");
+    let mut stale = vfat.create_file("/removed.bin").expect("create file");
+    stale.write_all(&[1; 5000]).expect("write file");
+    vfat.remove("/removed.bin", false).expect("remove file");
+
+    // the new file reuses the removed file's slot and clusters
+    vfat.create_file("/reused.bin").expect("create file")
+        .write_all(&[2; 5000]).expect("write file");
+    assert_eq!(stale.write(&[3; 100]).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(stale.set_len(0).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(stale.read_at(0, &mut [0; 10]).unwrap_err().kind(), ErrorKind::NotFound);
+
+    drop(stale);
+    drop(vfat);
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert!(remounted.open("/removed.bin").is_err());
+    assert!(read_file(&remounted, "/reused.bin") == vec![2; 5000], "reused file is intact");
+}
+
+#[test]
+fn test_rename() {
+    use std::io::ErrorKind;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    vfat.create_dir("/src/inner", true).expect("create directories");
+    vfat.create_dir("/dst", false).expect("create directory");
+    vfat.create_file("/src/inner/file.txt").expect("create file")
+        .write_all(b"some data").expect("write file");
+
+    vfat.rename("/src/inner/file.txt", "/src/inner/A Much Longer Name.txt").expect("rename");
+    assert_eq!(read_file(&vfat, "/src/inner/a much longer name.txt"), b"some data");
+    let e = vfat.open("/src/inner/file.txt").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    vfat.create_file("/dst/taken").expect("create file");
+    let e = vfat.rename("/src/inner/A Much Longer Name.txt", "/dst/taken").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+    let e = vfat.rename("/src/missing", "/dst/missing").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = vfat.rename("/src", "/src/inner/src").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    vfat.rename("/src/inner", "/dst/inner").expect("move directory");
+    assert_eq!(read_file(&vfat, "/dst/inner/A Much Longer Name.txt"), b"some data");
+    let parent = vfat.open_dir("/dst/inner").expect("moved directory")
+        .find("..").expect("'..' entry")
+        .into_dir().expect("'..' is a directory");
+    assert!(parent.find("taken").is_ok(), "'..' refers to the new parent");
+    assert!(vfat.open_dir("/src").expect("old parent").is_empty().unwrap());
+}
+
+#[test]
+fn test_renamed_file_handle() {
+    use std::io::SeekFrom;
+
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let vfat = VFat::from(image.clone()).expect("mount image");
+    vfat.create_file("/before.txt").expect("create file")
+        .write_all(b"original").expect("write file");
+    let mut handle = vfat.open_file("/before.txt").expect("open file");
+    vfat.rename("/before.txt", "/A Name Needing Long Entries.txt").expect("rename");
+
+    // the new file reuses the renamed file's old slot; the handle follows the
+    // renamed file to its new slot
+    vfat.create_file("/before.txt").expect("create file")
+        .write_all(b"newcomer").expect("write file");
+    handle.seek(SeekFrom::End(0)).expect("seek to end");
+    handle.write_all(b" and more").expect("write renamed file");
+    handle.set_len(12).expect("truncate renamed file");
+
+    drop(handle);
+    drop(vfat);
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert_eq!(read_file(&remounted, "/A Name Needing Long Entries.txt"), b"original and");
+    assert_eq!(read_file(&remounted, "/before.txt"), b"newcomer");
+}
+
+#[test]
+fn test_empty_file_handles() {
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let mut empty = vfat.create_file("/empty.bin").expect("create file");
+    let mut other = vfat.open_file("/empty.bin").expect("open file");
+
+    // the first cluster is allocated through the other handle
+    other.write_all(b"first cluster").expect("write file");
+    let mut buf = Vec::new();
+    empty.read_to_end(&mut buf).expect("read file");
+    assert_eq!(buf, b"first cluster");
+
+    empty.set_len(0).expect("truncate to empty");
+    assert_eq!(other.size(), 0);
+    assert_eq!(other.write_at(0, b"again").expect("write file"), 5);
+    assert_eq!(empty.size(), 5);
+    assert_eq!(read_file(&vfat, "/empty.bin"), b"again");
+}
+
+#[test]
+fn test_shared_file_handles() {
+    use std::io::SeekFrom;
+
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let options = MountOptions { free_bitmap: true, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(image.clone(), options.clone()).expect("mount image");
+    let cluster_size = vfat.borrow().cluster_size();
+    let free = vfat.borrow_mut().free_clusters().unwrap();
+
+    let mut first = vfat.create_file("/shared.bin").expect("create file");
+    let mut second = vfat.open_file("/shared.bin").expect("open file");
+    first.write_all(&vec![1; cluster_size * 2]).expect("write file");
+    assert_eq!(second.size(), cluster_size as u64 * 2, "size is shared");
+    second.seek(SeekFrom::End(0)).expect("seek to end");
+    second.write_all(&vec![2; cluster_size * 2]).expect("append file");
+    first.seek(SeekFrom::End(0)).expect("seek to end");
+    first.write_all(&vec![3; cluster_size]).expect("append file");
+
+    let mut expected = vec![1; cluster_size * 2];
+    expected.extend(vec![2; cluster_size * 2]);
+    expected.extend(vec![3; cluster_size]);
+    assert!(read_file(&vfat, "/shared.bin") == expected, "appends are chained");
+
+    first.set_len(cluster_size as u64 + 10).expect("truncate");
+    assert_eq!(second.read_at(cluster_size as u64 * 3, &mut [0; 10]).unwrap(), 0);
+    second.write_at(cluster_size as u64 + 10, &[4; 10]).expect("append file");
+    expected.truncate(cluster_size + 10);
+    expected.extend(&[4; 10]);
+    assert!(read_file(&vfat, "/shared.bin") == expected, "truncation is shared");
+
+    drop(first);
+    drop(second);
+    vfat.remove("/shared.bin", false).expect("remove file");
+    drop(vfat);
+    let remounted = VFat::from_with_options(Cursor::new(image.snapshot()), options).expect("remount image");
+    assert_eq!(remounted.borrow_mut().free_clusters().unwrap(), free, "no clusters are orphaned");
+}
+
+#[test]
+fn test_seek() {
+    use std::io::{ErrorKind, SeekFrom};
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let cluster_size = vfat.borrow().cluster_size();
+    let data: Vec<u8> = (0..(cluster_size * 4 + 100)).map(|i| (i % 253) as u8).collect();
+    let mut file = vfat.create_file("/seek.bin").expect("create file");
+    file.write_all(&data).expect("write file");
+
+    let positions = [0, 1, cluster_size - 1, cluster_size, cluster_size * 3 + 7, 5, data.len() - 1];
+    for &pos in positions.iter() {
+        assert_eq!(file.seek(SeekFrom::Start(pos as u64)).expect("seek"), pos as u64);
+        let mut byte = [0];
+        file.read_exact(&mut byte).expect("read byte");
+        assert_eq!(byte[0], data[pos], "byte at offset {}", pos);
+    }
+
+    assert_eq!(file.seek(SeekFrom::End(-10)).expect("seek"), data.len() as u64 - 10);
+    assert_eq!(file.seek(SeekFrom::Current(-(cluster_size as i64))).expect("seek"),
+               (data.len() - 10 - cluster_size) as u64);
+    assert_eq!(file.seek(SeekFrom::End(0)).expect("seek to end"), data.len() as u64);
+
+    let e = file.seek(SeekFrom::End(1)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = file.seek(SeekFrom::Current(-(data.len() as i64) - 1)).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    file.seek(SeekFrom::Start(cluster_size as u64)).expect("seek");
+    file.write_all(b"overwritten").expect("write after seek");
+    let contents = read_file(&vfat, "/seek.bin");
+    assert_eq!(&contents[cluster_size..(cluster_size + 11)], b"overwritten");
+    assert_eq!(contents.len(), data.len());
+}
+
+#[test]
+fn test_positional_io() {
+    use std::io::{ErrorKind, SeekFrom};
+    use std::sync::Arc;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let cluster_size = vfat.borrow().cluster_size();
+    let mut data: Vec<u8> = (0..(cluster_size * 3 + 50)).map(|i| (i % 249) as u8).collect();
+    let mut file = vfat.create_file("/positional.bin").expect("create file");
+    assert_eq!(file.write_at(0, &data).expect("write at start"), data.len());
+    file.seek(SeekFrom::Start(10)).expect("seek");
+
+    let mut buf = vec![0; cluster_size + 20];
+    assert_eq!(file.read_at(cluster_size as u64 - 10, &mut buf).expect("read at"), buf.len());
+    assert!(&buf[..] == &data[(cluster_size - 10)..(cluster_size * 2 + 10)]);
+    assert_eq!(file.read_at(data.len() as u64 - 5, &mut buf).expect("read at end"), 5);
+    assert_eq!(file.read_at(data.len() as u64 + 5, &mut buf).expect("read past end"), 0);
+    assert_eq!(file.seek(SeekFrom::Current(0)).expect("seek"), 10, "position is unchanged");
+
+    // overwrite across a cluster boundary and extend past the end
+    assert_eq!(file.write_at(cluster_size as u64 - 2, b"abcd").expect("write at"), 4);
+    data[(cluster_size - 2)..(cluster_size + 2)].copy_from_slice(b"abcd");
+    let tail = vec![0xEE; cluster_size];
+    assert_eq!(file.write_at(data.len() as u64, &tail).expect("extend"), tail.len());
+    data.extend_from_slice(&tail);
+    assert_eq!(file.size(), data.len() as u64);
+    let e = file.write_at(data.len() as u64 + 1, b"gap").unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+
+    let mut contents = vec![];
+    file.read_to_end(&mut contents).expect("read from position");
+    assert!(&contents[..] == &data[10..]);
+    assert_eq!(read_file(&vfat, "/positional.bin"), data);
+
+    let file = Arc::new(file);
+    let data = Arc::new(data);
+    let threads: Vec<_> = (0..4)
+        .map(|i| {
+            let (file, data) = (file.clone(), data.clone());
+            ::std::thread::spawn(move || {
+                let offset = i * cluster_size / 2 + i;
+                let mut buf = vec![0; cluster_size + 7];
+                file.read_at(offset as u64, &mut buf).expect("concurrent read at");
+                assert!(&buf[..] == &data[offset..(offset + buf.len())]);
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("reader thread");
+    }
+}
+
+#[test]
+fn test_fragmented_file() {
+    use std::io::SeekFrom;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let cluster_size = vfat.borrow().cluster_size();
+    let mut a = vfat.create_file("/a.bin").expect("create file");
+    let mut b = vfat.create_file("/b.bin").expect("create file");
+
+    // interleave writes so that the two files' chains are fragmented
+    let data: Vec<u8> = (0..(cluster_size * 9 + 17)).map(|i| (i % 251) as u8).collect();
+    for (i, chunk) in data.chunks(cluster_size * 2).enumerate() {
+        a.write_all(chunk).expect("write a");
+        b.write_all(&vec![i as u8; cluster_size]).expect("write b");
+    }
+
+    let chain = vfat.borrow_mut().chain(a.start_cluster()).expect("chain");
+    assert!(chain.windows(2).any(|w| w[1].id() != w[0].id() + 1), "chain is fragmented");
+
+    let mut reopened = vfat.open_file("/a.bin").expect("open file");
+    let mut contents = vec![];
+    reopened.read_to_end(&mut contents).expect("read file");
+    assert!(contents == data, "contents match across extents");
+
+    let positions = [cluster_size * 8 + 3, 0, cluster_size * 2 - 1, cluster_size * 5, data.len() - 1];
+    for &pos in positions.iter() {
+        reopened.seek(SeekFrom::Start(pos as u64)).expect("seek");
+        let mut buf = vec![0; min(cluster_size * 3, data.len() - pos)];
+        reopened.read_exact(&mut buf).expect("read after seek");
+        assert!(&buf[..] == &data[pos..(pos + buf.len())], "read at offset {}", pos);
+    }
+
+    reopened.seek(SeekFrom::End(0)).expect("seek to end");
+    reopened.write_all(&[0xEE; 10]).expect("extend file");
+    reopened.seek(SeekFrom::Start(data.len() as u64)).expect("seek");
+    let mut tail = vec![];
+    reopened.read_to_end(&mut tail).expect("read tail");
+    assert_eq!(tail, vec![0xEE; 10]);
+}
+
+#[test]
+fn test_direct_io() {
+    use std::io::SeekFrom;
+
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let vfat = VFat::from(image.clone()).expect("mount image");
+    let cluster_size = vfat.borrow().cluster_size();
+    let data: Vec<u8> = (0..(cluster_size * 6 + 99)).map(|i| (i % 241) as u8).collect();
+    let mut file = vfat.create_file("/direct.bin").expect("create file");
+    file.write_all(&data).expect("write file");
+
+    // unsynced data is still observed by direct reads
+    file.set_direct_io(true);
+    assert!(file.direct_io());
+    file.seek(SeekFrom::Start(0)).expect("seek");
+    let mut contents = vec![];
+    file.read_to_end(&mut contents).expect("direct read");
+    assert!(contents == data, "direct read sees cached data");
+
+    file.sync().expect("sync");
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    let mut file = remounted.open_file("/direct.bin").expect("open file");
+    file.set_direct_io(true);
+    for &(pos, len) in [(0, cluster_size * 2), (cluster_size, cluster_size * 3 + 5), (7, cluster_size * 2)].iter() {
+        file.seek(SeekFrom::Start(pos as u64)).expect("seek");
+        let mut buf = vec![0; len];
+        file.read_exact(&mut buf).expect("read");
+        assert!(&buf[..] == &data[pos..(pos + len)], "direct read at offset {}", pos);
+    }
+}
+
+#[test]
+fn test_stats() {
+    let options = MountOptions { cache_capacity: Some(16), ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(resource_data!("mock1.fat32.img")), options)
+        .expect("mount image");
+    let cluster_size = vfat.borrow().cluster_size();
+    let data = vec![0x5A; cluster_size * 32];
+    vfat.create_file("/stats.bin").expect("create file")
+        .write_all(&data).expect("write file");
+    vfat.borrow_mut().sync().expect("sync");
+    let stats = vfat.borrow().stats();
+    assert!(stats.write_backs > 0 && stats.sectors_written >= stats.write_backs);
+    assert!(stats.fat_lookups > 0);
+
+    vfat.borrow_mut().reset_stats();
+    assert_eq!(vfat.borrow().stats(), Stats::default());
+
+    let mut file = vfat.open_file("/stats.bin").expect("open file");
+    file.set_direct_io(true);
+    let mut contents = vec![0; data.len()];
+    file.read_exact(&mut contents).expect("direct read");
+    assert!(contents == data);
+    let direct = vfat.borrow().stats();
+    assert!(direct.sectors_read >= (data.len() / 512) as u64, "direct reads are counted");
+    assert!(direct.cache_misses < (data.len() / 512) as u64, "direct reads bypass the cache");
+
+    vfat.borrow_mut().reset_stats();
+    assert_eq!(read_file(&vfat, "/stats.bin"), data);
+    let first = vfat.borrow().stats();
+    assert!(first.cache_misses > 0 && first.sectors_read > 0);
+    assert!(first.evictions > 0, "reading more than the cache holds evicts");
+
+    vfat.borrow_mut().reset_stats();
+    let mut buf = [0; 16];
+    vfat.open_file("/stats.bin").expect("open file")
+        .read_exact(&mut buf).expect("read file");
+    vfat.open_file("/stats.bin").expect("open file")
+        .read_exact(&mut buf).expect("read file");
+    let second = vfat.borrow().stats();
+    assert!(second.cache_hits > 0);
+    assert_eq!(second.write_backs, 0);
+}
+
+#[test]
+fn test_set_len() {
+    use std::io::SeekFrom;
+
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let cluster_size = vfat.borrow().cluster_size();
+    let data: Vec<u8> = (0..(cluster_size * 3)).map(|i| (i % 253 + 1) as u8).collect();
+    let mut file = vfat.create_file("/config.txt").expect("create file");
+    file.write_all(&data).expect("write file");
+
+    file.set_len(cluster_size as u64 + 10).expect("truncate");
+    assert_eq!(file.size(), cluster_size as u64 + 10);
+    assert_eq!(file.seek(SeekFrom::Current(0)).unwrap(), cluster_size as u64 + 10);
+    assert_eq!(read_file(&vfat, "/config.txt"), &data[..(cluster_size + 10)]);
+
+    file.set_len(cluster_size as u64 * 2 + 1).expect("extend");
+    let contents = read_file(&vfat, "/config.txt");
+    assert_eq!(contents.len(), cluster_size * 2 + 1);
+    assert_eq!(&contents[..(cluster_size + 10)], &data[..(cluster_size + 10)]);
+    assert!(contents[(cluster_size + 10)..].iter().all(|&b| b == 0));
+
+    file.set_len(0).expect("truncate to zero");
+    assert_eq!(read_file(&vfat, "/config.txt").len(), 0);
+    file.write_all(b"fresh").expect("write after truncate");
+    assert_eq!(read_file(&vfat, "/config.txt"), b"fresh");
+    drop(file);
+
+    // a recorded size beyond the end of the chain is reported, not trusted
+    let loc = vfat.open_file("/config.txt").unwrap().dirent().expect("file has an entry");
+    vfat.borrow_mut().dirent_mut(loc).unwrap().set_size(cluster_size as u32 * 4);
+    let e = vfat.open_file("/config.txt").unwrap().set_len(cluster_size as u64 * 3).unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_free_cluster_accounting() {
+    let vfat = vfat_from_resource_in_memory!("mock1.fat32.img");
+    let cluster_size = vfat.borrow().cluster_size();
+    let free = vfat.borrow_mut().free_clusters().expect("free cluster count");
+
+    vfat.create_file("/alloc.bin").expect("create file")
+        .write_all(&vec![0xAB; cluster_size * 5]).expect("write file");
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 5);
+
+    vfat.open_file("/alloc.bin").expect("file exists")
+        .set_len(cluster_size as u64 * 2).expect("truncate");
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 2);
+
+    vfat.remove("/alloc.bin", false).expect("remove file");
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free);
+
+    // without the bitmap, the FAT is scanned for a run
+    let run = vfat.borrow().find_free_clusters(8).expect("find free clusters");
+    assert!(run.windows(2).all(|w| w[1].id() == w[0].id() + 1), "free clusters are contiguous");
+    for &cluster in run.iter() {
+        assert_eq!(vfat.borrow().fat_entry(cluster).unwrap().status(), Status::Free);
+    }
+    assert!(vfat.borrow().find_free_clusters(free + 1).is_err());
+}
+
+#[test]
+fn test_cluster_bitmap_bounds() {
+    use vfat::{ClusterBitmap, Cluster};
+
+    let mut bitmap = ClusterBitmap::new(10);
+    assert!(!bitmap.is_free(Cluster::from(0)) && !bitmap.is_free(Cluster::from(1)));
+    assert!(!bitmap.is_free(Cluster::from(12)), "clusters past the data region are not free");
+    assert!(bitmap.is_free(Cluster::from(2)) && bitmap.is_free(Cluster::from(11)));
+    bitmap.set_used(Cluster::from(11), true);
+    assert_eq!(bitmap.free_count(), 9);
+    assert_eq!(bitmap.find_free(Cluster::from(11)).map(|c| c.id()), Some(2));
+}
+
+#[test]
+fn test_free_bitmap() {
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let options = MountOptions { free_bitmap: true, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(image.clone(), options.clone()).expect("mount image");
+    let cluster_size = vfat.borrow().cluster_size();
+    let free = vfat.borrow_mut().free_clusters().expect("free cluster count");
+
+    let run = vfat.borrow_mut().find_free_clusters(8).expect("find free clusters");
+    assert_eq!(run.len(), 8);
+    assert!(run.windows(2).all(|w| w[1].id() == w[0].id() + 1), "free clusters are contiguous");
+    for &cluster in run.iter() {
+        assert_eq!(vfat.borrow_mut().fat_entry(cluster).unwrap().status(), Status::Free);
+    }
+    assert!(vfat.borrow_mut().find_free_clusters(free + 1).is_err());
+
+    vfat.create_file("/a.bin").expect("create file")
+        .write_all(&vec![1; cluster_size * 3]).expect("write file");
+    vfat.create_file("/b.bin").expect("create file")
+        .write_all(&vec![2; cluster_size * 4]).expect("write file");
+    vfat.remove("/a.bin", false).expect("remove file");
+    assert_eq!(vfat.borrow_mut().free_clusters().unwrap(), free - 4);
+
+    vfat.borrow_mut().sync().expect("sync");
+    let rebuilt = VFat::from_with_options(Cursor::new(image.snapshot()), options).expect("remount image");
+    assert_eq!(rebuilt.borrow_mut().free_clusters().unwrap(), free - 4);
+}
+
+#[test]
+fn test_dir_index() {
+    let options = MountOptions { dir_index: true, ..MountOptions::default() };
+    let vfat = VFat::from_with_options(Cursor::new(resource_data!("mock1.fat32.img")), options)
+        .expect("mount image");
+    vfat.create_dir("/photos", false).expect("create dir");
+    for i in 0..100 {
+        vfat.create_file(format!("/photos/Photo {:03}.JPG", i)).expect("create file");
+    }
+
+    for &i in [0, 42, 99].iter() {
+        let file = vfat.open_file(format!("/photos/photo {:03}.jpg", i)).expect("indexed lookup");
+        assert_eq!(file.name, format!("Photo {:03}.JPG", i));
+    }
+    assert!(vfat.open("/photos/missing.jpg").is_err());
+
+    // modifications are reflected in subsequent lookups
+    vfat.open_file("/photos/Photo 042.JPG").expect("open file")
+        .write_all(b"pixels").expect("write file");
+    assert_eq!(vfat.open_file("/photos/PHOTO 042.JPG").expect("open file").size(), 6);
+    vfat.rename("/photos/Photo 042.JPG", "/photos/Renamed.JPG").expect("rename");
+    assert!(vfat.open("/photos/Photo 042.JPG").is_err());
+    assert_eq!(read_file(&vfat, "/photos/renamed.jpg"), b"pixels");
+    vfat.remove("/photos/Photo 000.JPG", false).expect("remove");
+    assert!(vfat.open("/photos/Photo 000.JPG").is_err());
+    vfat.create_file("/photos/Photo 000.JPG").expect("recreate file");
+    vfat.open_file("/photos/photo 000.jpg").expect("recreated file is found");
+
+    vfat.remove("/photos", true).expect("remove dir");
+    assert!(vfat.open("/photos").is_err());
+    vfat.create_dir("/photos", false).expect("create dir");
+    assert!(vfat.open("/photos/Photo 001.JPG").is_err(), "index of a removed directory is discarded");
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    data[offset] as u16 | (data[offset + 1] as u16) << 8
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    read_u16(data, offset) as u32 | (read_u16(data, offset + 2) as u32) << 16
+}
+
+/// Returns the byte offset of the BPB and the byte range of each FAT in the
+/// image `data` whose first partition is a FAT32 file system.
+fn fat_ranges(data: &[u8]) -> (usize, Vec<::std::ops::Range<usize>>) {
+    let bpb = read_u32(data, 446 + 8) as usize * 512;
+    let sector_size = read_u16(data, bpb + 11) as usize;
+    let reserved = read_u16(data, bpb + 14) as usize;
+    let fat_count = data[bpb + 16] as usize;
+    let fat_size = read_u32(data, bpb + 36) as usize * sector_size;
+    let ranges = (0..fat_count)
+        .map(|i| {
+            let start = bpb + reserved * sector_size + i * fat_size;
+            start..(start + fat_size)
+        })
+        .collect();
+    (bpb, ranges)
+}
+
+#[test]
+fn test_active_fat() {
+    let mut data = resource_data!("mock1.fat32.img");
+    let (bpb, fats) = fat_ranges(&data);
+    assert!(fats.len() >= 2, "mock image has a backup FAT");
+    let expected = hash_files_recursive_from(VFat::from(Cursor::new(data.clone())).unwrap(), "/");
+
+    // with mirroring disabled and FAT 1 active, FAT 0 is never read
+    data[bpb + 40] = 0x81;
+    for byte in data[fats[0].clone()].iter_mut() {
+        *byte = 0;
+    }
+
+    let vfat = VFat::from(Cursor::new(data)).expect("mount with FAT 1 active");
+    let hash = hash_files_recursive_from(vfat.clone(), "/");
+    assert_hash_eq!("mock 1 file hashes with FAT 1 active", hash, expected);
+
+    vfat.create_file("/written.bin").expect("create file")
+        .write_all(&[0x5A; 10000]).expect("write file");
+    assert_eq!(read_file(&vfat, "/written.bin"), &[0x5A; 10000][..]);
+}
+
+/// The FAT32 volume of `mock1.fat32.img`, for placing in partition tables.
+struct MockVolume {
+    /// The whole MBR-partitioned image.
+    image: Vec<u8>,
+    /// The contents of the image's first partition.
+    volume: Vec<u8>,
+    /// The hash of every file in the volume.
+    expected: String,
+}
+
+impl MockVolume {
+    fn new() -> MockVolume {
+        let image = resource_data!("mock1.fat32.img");
+        let start = read_u32(&image, 446 + 8) as usize * 512;
+        let len = read_u32(&image, 446 + 12) as usize * 512;
+        let volume = image[start..min(start + len, image.len())].to_vec();
+        let expected = hash_files_recursive_from(VFat::from(Cursor::new(image.clone())).unwrap(), "/");
+        MockVolume { image, volume, expected }
+    }
+
+    /// Asserts that `vfat`, described by `name`, holds the volume's files.
+    fn assert_mounted(&self, name: &str, vfat: Shared<VFat>) {
+        assert_hash_eq!(name, hash_files_recursive_from(vfat, "/"), &self.expected);
+    }
+}
+
+/// Returns an image with an MBR whose primary partitions are `parts`: a type
+/// and the partition's contents. Entries of type 0 are left empty.
+fn mbr_image(parts: &[(u8, &[u8])]) -> Vec<u8> {
+    let mut data = vec![0; 8 * 512];
+    for (i, &(kind, contents)) in parts.iter().enumerate() {
+        if kind == 0 {
+            continue;
+        }
+
+        let (start, sectors) = (data.len() as u32 / 512, contents.len() as u32 / 512);
+        set_partition_entry(&mut data, i, kind, start, sectors);
+        data.extend_from_slice(contents);
+    }
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    data
+}
+
+/// Sets entry `slot` of the partition table in the MBR or EBR `sector`.
+fn set_partition_entry(sector: &mut [u8], slot: usize, kind: u8, start: u32, sectors: u32) {
+    let entry = 446 + slot * 16;
+    sector[entry + 4] = kind;
+    for j in 0..4 {
+        sector[entry + 8 + j] = (start >> (j * 8)) as u8;
+        sector[entry + 12 + j] = (sectors >> (j * 8)) as u8;
+    }
+}
+
+#[test]
+fn test_partition_selection() {
+    let mock = MockVolume::new();
+    let image = mbr_image(&[(0x83, &[0xAB; 4096]), (0, &[]), (0x0C, &mock.volume)]);
+
+    let scanned = VFat::from(Cursor::new(image.clone())).expect("mount first FAT32 partition");
+    mock.assert_mounted("first FAT32 partition", scanned);
+    let selected = VFat::from_partition(Cursor::new(image.clone()), 2).expect("mount partition 2");
+    mock.assert_mounted("partition 2", selected);
+
+    let e = VFat::from_partition(Cursor::new(image.clone()), 1).unwrap_err();
+    expect_variant!(e, ::vfat::Error::EmptyPartition(1));
+    let e = VFat::from_partition(Cursor::new(image.clone()), 3).unwrap_err();
+    expect_variant!(e, ::vfat::Error::EmptyPartition(3));
+    let e = VFat::from_partition(Cursor::new(image.clone()), 4).unwrap_err();
+    expect_variant!(e, ::vfat::Error::NotFound);
+    let e = VFat::from_partition(Cursor::new(image), 0).unwrap_err();
+    expect_variant!(e, ::vfat::Error::BadSignature);
+
+    let image = mbr_image(&[(0x83, &[0; 4096]), (0x07, &[0; 4096])]);
+    let e = VFat::from(Cursor::new(image)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::NotFound);
+}
+
+#[test]
+fn test_logical_partitions() {
+    let mock = MockVolume::new();
+    let volume_sectors = mock.volume.len() as u32 / 512;
+
+    // the extended partition at sector 8 holds an 8 sector logical partition
+    // followed by the FAT32 volume, each preceded by its EBR
+    let base = 8;
+    let mut image = vec![0; 512 * (base as usize + 1)];
+    set_partition_entry(&mut image, 0, 0x83, 1, 7);
+    set_partition_entry(&mut image, 1, 0x0F, base, 10 + volume_sectors);
+    image[510..512].copy_from_slice(&[0x55, 0xAA]);
+    let ebr = (base as usize) * 512;
+    set_partition_entry(&mut image[ebr..], 0, 0x83, 1, 8);
+    set_partition_entry(&mut image[ebr..], 1, 0x05, 9, 1 + volume_sectors);
+    image[(ebr + 510)..(ebr + 512)].copy_from_slice(&[0x55, 0xAA]);
+    image.extend_from_slice(&[0xAB; 8 * 512]);
+    let mut second_ebr = [0; 512];
+    set_partition_entry(&mut second_ebr, 0, 0x0C, 1, volume_sectors);
+    second_ebr[510..512].copy_from_slice(&[0x55, 0xAA]);
+    image.extend_from_slice(&second_ebr);
+    image.extend_from_slice(&mock.volume);
+
+    let mbr = MasterBootRecord::from(Cursor::new(image.clone())).expect("valid MBR");
+    let logical = mbr.logical_partitions(Cursor::new(image.clone())).expect("valid EBR chain");
+    let starts: Vec<u32> = logical.iter().map(|entry| entry.start_sector).collect();
+    assert_eq!(starts, vec![base + 1, base + 10]);
+
+    let scanned = VFat::from(Cursor::new(image.clone())).expect("mount logical FAT32 partition");
+    mock.assert_mounted("logical partition", scanned);
+    let selected = VFat::from_partition(Cursor::new(image.clone()), 5).expect("mount partition 5");
+    mock.assert_mounted("partition 5", selected);
+    let e = VFat::from_partition(Cursor::new(image.clone()), 6).unwrap_err();
+    expect_variant!(e, ::vfat::Error::NotFound);
+
+    // a chain that links back to its first EBR is rejected
+    let second = (base as usize + 9) * 512;
+    set_partition_entry(&mut image[second..], 1, 0x05, 0, 1);
+    let e = mbr.logical_partitions(Cursor::new(image.clone())).unwrap_err();
+    expect_variant!(e, ::mbr::Error::BadExtendedChain);
+}
+
+#[test]
+fn test_superfloppy() {
+    let mock = MockVolume::new();
+    let mut volume = mock.volume.clone();
+
+    let mut bpb = [0; 512];
+    bpb.copy_from_slice(&volume[..512]);
+    assert!(BiosParameterBlock::from(Cursor::new(&mut bpb[..]), 0).unwrap().is_fat32());
+    let mut mbr = [0; 512];
+    mbr.copy_from_slice(&mock.image[..512]);
+    assert!(!BiosParameterBlock::from(Cursor::new(&mut mbr[..]), 0).unwrap().is_fat32());
+
+    let vfat = VFat::from(Cursor::new(volume.clone())).expect("mount superfloppy");
+    mock.assert_mounted("superfloppy", vfat.clone());
+    vfat.create_file("/floppy.txt").expect("create file")
+        .write_all(b"no partition table").expect("write file");
+    assert_eq!(read_file(&vfat, "/floppy.txt"), b"no partition table");
+
+    // a boot sector with an insane geometry is not taken for a volume
+    volume[13] = 3;
+    assert!(VFat::from(Cursor::new(volume)).is_err());
+}
+
+/// Returns a freshly formatted FAT12 or FAT16 volume of `total_sectors`
+/// 512-byte sectors with one sector per cluster, two FATs of `fat_sectors`
+/// sectors each, and a fixed root directory of `root_entries` entries.
+fn fat16_volume(total_sectors: u16, fat_sectors: u16, root_entries: u16, fat12: bool) -> Vec<u8> {
+    let mut data = vec![0; total_sectors as usize * 512];
+    data[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    data[11..13].copy_from_slice(&[0x00, 0x02]);
+    data[13] = 1;
+    data[14..16].copy_from_slice(&[1, 0]);
+    data[16] = 2;
+    data[17..19].copy_from_slice(&[root_entries as u8, (root_entries >> 8) as u8]);
+    data[19..21].copy_from_slice(&[total_sectors as u8, (total_sectors >> 8) as u8]);
+    data[21] = 0xF8;
+    data[22..24].copy_from_slice(&[fat_sectors as u8, (fat_sectors >> 8) as u8]);
+    data[38] = 0x29;
+    data[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let reserved: &[u8] = if fat12 { &[0xF8, 0xFF, 0xFF] } else { &[0xF8, 0xFF, 0xFF, 0xFF] };
+    for i in 0..2 {
+        let fat = 512 * (1 + i * fat_sectors as usize);
+        data[fat..(fat + reserved.len())].copy_from_slice(reserved);
+    }
+    data
+}
+
+#[test]
+fn test_fat12_fat16() {
+    use vfat::FatType;
+
+    let volumes = [
+        (FatType::Fat12, fat16_volume(2880, 9, 64, true)),
+        (FatType::Fat16, fat16_volume(8192, 32, 512, false)),
+    ];
+    for &(fat_type, ref volume) in volumes.iter() {
+        let image = SharedImage::new(volume.clone());
+        let vfat = VFat::from(image.clone()).expect("mount volume");
+        assert_eq!(vfat.borrow().fat_type(), fat_type);
+
+        // interleaved writes fragment the chains across odd and even entries
+        let first: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+        let second: Vec<u8> = (0..20000).map(|i| (i % 241) as u8).collect();
+        {
+            let mut a = vfat.create_file("/first.bin").expect("create file");
+            let mut b = vfat.create_file("/second.bin").expect("create file");
+            for (x, y) in first.chunks(700).zip(second.chunks(700)) {
+                a.write_all(x).expect("write file");
+                b.write_all(y).expect("write file");
+            }
+        }
+        assert!(read_file(&vfat, "/first.bin") == first, "{:?} file contents", fat_type);
+        assert!(read_file(&vfat, "/second.bin") == second, "{:?} file contents", fat_type);
+
+        vfat.create_dir("/a/b", true).expect("create dirs");
+        vfat.create_file("/a/b/nested.txt").expect("create file")
+            .write_all(b"nested").expect("write file");
+        {
+            let parent = vfat.open_dir("/a").expect("open dir").find("..").expect("parent entry");
+            assert!(parent.as_dir().unwrap().find("first.bin").is_ok(), ".. refers to the root");
+        }
+
+        vfat.remove("/second.bin", false).expect("remove file");
+        drop(vfat);
+
+        let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount volume");
+        assert!(read_file(&remounted, "/first.bin") == first, "{:?} remounted contents", fat_type);
+        assert_eq!(read_file(&remounted, "/a/b/nested.txt"), b"nested");
+        assert!(remounted.open("/second.bin").is_err());
+
+        let partitioned = mbr_image(&[(0x06, volume)]);
+        let vfat = VFat::from(Cursor::new(partitioned)).expect("mount FAT16 partition");
+        assert_eq!(vfat.borrow().fat_type(), fat_type);
+    }
+
+    // the fixed root directory cannot grow
+    let vfat = VFat::from(Cursor::new(fat16_volume(2880, 9, 16, true))).expect("mount volume");
+    for i in 0..16 {
+        vfat.create_file(format!("/F{}.TXT", i)).expect("create file");
+    }
+    let e = vfat.create_file("/F16.TXT").unwrap_err();
+    assert_eq!(e.kind(), ::std::io::ErrorKind::Other);
+    assert_eq!(vfat.open_dir("/").unwrap().entries().unwrap().count(), 16);
+}
+
+#[test]
+fn test_invalid_geometry() {
+    let mut volume = fat16_volume(2880, 9, 64, true);
+    volume[13] = 0;
+    let image = mbr_image(&[(0x06, &volume)]);
+
+    let e = VFat::from_partition(Cursor::new(image.clone()), 0).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::InvalidData);
+    let e = VFat::from(Cursor::new(image)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::InvalidData);
+
+    // logical sectors must be whole multiples of the device's sectors
+    for &sector_bytes in [0u16, 256, 768].iter() {
+        let mut volume = fat16_volume(2880, 9, 64, true);
+        volume[11..13].copy_from_slice(&[sector_bytes as u8, (sector_bytes >> 8) as u8]);
+        let image = mbr_image(&[(0x06, &volume)]);
+        let e = VFat::from_partition(Cursor::new(image), 0).unwrap_err();
+        expect_variant!(e, ::vfat::Error::Io(ref e) if e.kind() == ::std::io::ErrorKind::InvalidData);
+    }
+}
+
+/// Returns an image with a protective MBR and a GPT with four entries, the
+/// first of which describe `parts`: a type, a name, and the partition's
+/// contents.
+fn gpt_image(parts: &[(Guid, &str, &[u8])]) -> Vec<u8> {
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..(offset + bytes.len())].copy_from_slice(bytes);
+    }
+
+    fn le(value: u64, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (value >> (i * 8)) as u8).collect()
+    }
+
+    let mut data = vec![0; 34 * 512];
+    let mut entries = vec![0; 4 * 128];
+    for (i, &(guid, name, contents)) in parts.iter().enumerate() {
+        let first = data.len() as u64 / 512;
+        data.extend_from_slice(contents);
+        let entry = &mut entries[(i * 128)..((i + 1) * 128)];
+        put(entry, 0, &guid.0);
+        put(entry, 16, &[i as u8 + 1; 16]);
+        put(entry, 32, &le(first, 8));
+        put(entry, 40, &le(first + contents.len() as u64 / 512 - 1, 8));
+        for (j, c) in name.encode_utf16().enumerate() {
+            put(entry, 56 + j * 2, &le(c as u64, 2));
+        }
+    }
+
+    let sectors = data.len() as u64 / 512;
+    put(&mut data, 446 + 4, &[0xEE]);
+    put(&mut data, 446 + 8, &le(1, 4));
+    put(&mut data, 446 + 12, &le(sectors - 1, 4));
+    put(&mut data, 510, &[0x55, 0xAA]);
+
+    let mut header = vec![0; 92];
+    put(&mut header, 0, b"EFI PART");
+    put(&mut header, 8, &le(0x10000, 4));
+    put(&mut header, 12, &le(92, 4));
+    put(&mut header, 24, &le(1, 8));
+    put(&mut header, 32, &le(sectors - 1, 8));
+    put(&mut header, 40, &le(34, 8));
+    put(&mut header, 48, &le(sectors - 34, 8));
+    put(&mut header, 72, &le(2, 8));
+    put(&mut header, 80, &le(4, 4));
+    put(&mut header, 84, &le(128, 4));
+    put(&mut header, 88, &le(::gpt::crc32(&entries) as u64, 4));
+    let crc = ::gpt::crc32(&header);
+    put(&mut header, 16, &le(crc as u64, 4));
+    put(&mut data, 512, &header);
+    put(&mut data, 1024, &entries);
+    data
+}
+
+#[test]
+fn test_gpt() {
+    const LINUX_DATA: Guid = Guid([
+        0xAF, 0x3D, 0xC6, 0x0F, 0x83, 0x84, 0x72, 0x47,
+        0x8E, 0x79, 0x3D, 0x69, 0xD8, 0x47, 0x7D, 0xE4,
+    ]);
+
+    let mock = MockVolume::new();
+    let image = gpt_image(&[(LINUX_DATA, "root", &[0; 4096]), (Guid::BASIC_DATA, "FAT volume", &mock.volume)]);
+
+    let gpt = GuidPartitionTable::from(Cursor::new(image.clone())).expect("valid GPT");
+    assert_eq!(gpt.entries.len(), 4);
+    assert_eq!(gpt.entries[1].name(), "FAT volume");
+    assert_eq!(Guid::BASIC_DATA.to_string(), "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7");
+    assert!(gpt.find(PartitionId::Index(2)).is_none(), "unused entries are not found");
+    assert_eq!({ gpt.find(PartitionId::Type(Guid::BASIC_DATA)).unwrap().first_lba }, 34 + 8);
+
+    let by_index = VFat::from_gpt(Cursor::new(image.clone()), PartitionId::Index(1)).expect("mount by index");
+    mock.assert_mounted("GPT partition by index", by_index);
+    let by_type = VFat::from_gpt(Cursor::new(image.clone()), PartitionId::Type(Guid::BASIC_DATA))
+        .expect("mount by type");
+    mock.assert_mounted("GPT partition by type", by_type);
+    let protective = VFat::from(Cursor::new(image.clone())).expect("mount through protective MBR");
+    mock.assert_mounted("GPT partition through MBR", protective);
+
+    let e = VFat::from_gpt(Cursor::new(image.clone()), PartitionId::Index(3)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::NotFound);
+
+    let mut corrupt = image.clone();
+    corrupt[512 + 40] ^= 1;
+    let e = GuidPartitionTable::from(Cursor::new(corrupt)).unwrap_err();
+    expect_variant!(e, ::gpt::Error::BadHeaderChecksum);
+
+    let mut corrupt = image.clone();
+    corrupt[1024 + 128 + 56] ^= 1;
+    let e = VFat::from_gpt(Cursor::new(corrupt), PartitionId::Index(1)).unwrap_err();
+    expect_variant!(e, ::vfat::Error::Gpt(::gpt::Error::BadEntriesChecksum));
+
+    let e = GuidPartitionTable::from(Cursor::new(mock.image)).unwrap_err();
+    expect_variant!(e, ::gpt::Error::BadSignature);
+}
+
+/// An in-memory image that remains accessible after being handed to `VFat`.
+#[derive(Clone)]
+struct SharedImage(::std::sync::Arc<::std::sync::Mutex<Cursor<Vec<u8>>>>);
+
+impl SharedImage {
+    fn new(data: Vec<u8>) -> SharedImage {
+        SharedImage(::std::sync::Arc::new(::std::sync::Mutex::new(Cursor::new(data))))
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        self.0.lock().unwrap().get_ref().clone()
+    }
+}
+
+impl BlockDevice for SharedImage {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.0.lock().unwrap().write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_sync() {
+    let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+    let vfat = VFat::from(image.clone()).expect("mount image");
+    let mut file = vfat.create_file("/synced.txt").expect("create file");
+    file.write_all(b"persisted data").expect("write file");
+
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert!(remounted.open("/synced.txt").is_err(), "unsynced data is not written back");
+
+    file.sync().expect("sync file");
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert_eq!(read_file(&remounted, "/synced.txt"), b"persisted data");
+
+    let data = image.snapshot();
+    let (_, fats) = fat_ranges(&data);
+    for fat in fats.iter().skip(1) {
+        assert!(data[fats[0].clone()] == data[fat.clone()], "FAT copies are mirrored");
+    }
+
+    drop(file);
+    vfat.remove("/synced.txt", false).expect("remove file");
+    drop(vfat);
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert!(remounted.open("/synced.txt").is_err(), "dropping the file system writes back");
+}
+
+#[test]
+fn test_active_fat_only_is_written() {
+    let mut data = resource_data!("mock1.fat32.img");
+    let (bpb, fats) = fat_ranges(&data);
+    data[bpb + 40] = 0x81;
+    let original = data.clone();
+
+    let image = SharedImage::new(data);
+    let vfat = VFat::from(image.clone()).expect("mount image");
+    vfat.create_file("/file").expect("create file")
+        .write_all(&[1; 5000]).expect("write file");
+    vfat.borrow_mut().sync().expect("sync");
+
+    let data = image.snapshot();
+    assert!(data[fats[0].clone()] == original[fats[0].clone()], "inactive FAT is unchanged");
+    assert!(data[fats[1].clone()] != original[fats[1].clone()], "active FAT is updated");
+}
+
+#[test]
+fn test_bounded_cache() {
+    let original = resource_data!("mock1.fat32.img");
+    let image = SharedImage::new(original.clone());
+    let options = MountOptions { cache_capacity: Some(4), ..MountOptions::default() };
+    let vfat = VFat::from_with_options(image.clone(), options).expect("mount image");
+
+    let data: Vec<u8> = (0..20000).map(|i| (i % 253) as u8).collect();
+    vfat.create_file("/evicted.bin").expect("create file")
+        .write_all(&data).expect("write file");
+    assert!(image.snapshot() != original, "evicted dirty sectors are written back");
+    assert_eq!(read_file(&vfat, "/evicted.bin"), data);
+
+    vfat.borrow_mut().sync().expect("sync");
+    let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+    assert_eq!(read_file(&remounted, "/evicted.bin"), data);
+
+    let options = MountOptions { cache_capacity: Some(0), ..MountOptions::default() };
+    let tiny = VFat::from_with_options(Cursor::new(image.snapshot()), options).expect("mount image");
+    assert_eq!(read_file(&tiny, "/evicted.bin"), data);
+}
+
+/// A device that only implements single-sector access and counts requests.
+struct CountingDevice {
+    inner: Cursor<Vec<u8>>,
+    requests: ::std::sync::Arc<::std::sync::atomic::AtomicUsize>,
+}
+
+impl BlockDevice for CountingDevice {
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        self.requests.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+        self.inner.read_sector(n, buf)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> ::std::io::Result<usize> {
+        self.requests.fetch_add(1, ::std::sync::atomic::Ordering::SeqCst);
+        self.inner.write_sector(n, buf)
+    }
+}
+
+#[test]
+fn test_read_write_sectors() {
+    let data: Vec<u8> = (0..(512 * 8)).map(|i| (i / 512 + i % 7) as u8).collect();
+
+    let mut cursor = Cursor::new(data.clone());
+    let mut buf = vec![0; 512 * 3];
+    assert_eq!(cursor.read_sectors(2, 3, &mut buf).unwrap(), 512 * 3);
+    assert_eq!(&buf[..], &data[(512 * 2)..(512 * 5)]);
+    assert_eq!(cursor.write_sectors(6, 4, &[0xAB; 512 * 4]).unwrap(), 512 * 4);
+    assert!(&cursor.get_ref()[(512 * 6)..] == &[0xAB; 512 * 4][..]);
+
+    let requests = ::std::sync::Arc::new(::std::sync::atomic::AtomicUsize::new(0));
+    let mut device = CountingDevice { inner: Cursor::new(data.clone()), requests: requests.clone() };
+    let mut buf = vec![0; 1000];
+    assert_eq!(device.read_sectors(1, 4, &mut buf).unwrap(), 1000);
+    assert_eq!(&buf[..], &data[512..1512]);
+    assert_eq!(requests.load(::std::sync::atomic::Ordering::SeqCst), 2);
+    assert_eq!(device.write_sectors(0, 1, &[0; 512 * 2]).unwrap(), 512);
+    assert_eq!(requests.load(::std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_cached_device_prefetch() {
+    let data: Vec<u8> = (0..(512 * 16)).map(|i| (i / 512) as u8).collect();
+    let mut device = CachedDevice::new(Cursor::new(data.clone()), Partition { start: 0, sector_size: 1024 });
+
+    device.prefetch(2, 4).expect("prefetch");
+    for sector in 2..6 {
+        let start = sector as usize * 1024;
+        assert_eq!(device.get(sector).unwrap(), &data[start..(start + 1024)]);
+    }
+
+    device.get_mut(3).unwrap()[0] = 0xFF;
+    device.prefetch(0, 8).expect("prefetch");
+    assert_eq!(device.get(3).unwrap()[0], 0xFF, "cached sectors are not reloaded");
+    assert_eq!(device.get(7).unwrap(), &data[(7 * 1024)..(8 * 1024)]);
+}
+
+#[test]
+fn test_write_policies() {
+    let data: Vec<u8> = (0..3000).map(|i| (i % 239) as u8 + 1).collect();
+    let mount = |metadata_policy, data_policy| {
+        let image = SharedImage::new(resource_data!("mock1.fat32.img"));
+        let options = MountOptions { metadata_policy, data_policy, ..MountOptions::default() };
+        let vfat = VFat::from_with_options(image.clone(), options).expect("mount image");
+        let mut file = vfat.create_file("/policy.bin").expect("create file");
+        file.write_all(&data).expect("write file");
+        vfat.create_dir("/policy dir", false).expect("create dir");
+        let remounted = VFat::from(Cursor::new(image.snapshot())).expect("remount image");
+        (vfat, file, remounted)
+    };
+
+    let (_vfat, _file, remounted) = mount(WritePolicy::WriteBack, WritePolicy::WriteBack);
+    assert!(remounted.open("/policy.bin").is_err(), "write-back defers all writes");
+
+    let (vfat, _file, remounted) = mount(WritePolicy::WriteThrough, WritePolicy::WriteBack);
+    let file = remounted.open_file("/policy.bin").expect("metadata is written through");
+    assert_eq!(file.size(), data.len() as u64);
+    assert!(read_file(&remounted, "/policy.bin") != data, "file data is written back");
+    remounted.open_dir("/policy dir").expect("directory is written through");
+    assert_eq!(remounted.borrow_mut().free_clusters().unwrap(),
+               vfat.borrow_mut().free_clusters().unwrap(), "FSInfo is written through");
+
+    let (_vfat, _file, remounted) = mount(WritePolicy::WriteThrough, WritePolicy::WriteThrough);
+    assert_eq!(read_file(&remounted, "/policy.bin"), data);
+}
+
+#[test]
+fn test_concurrent_readers() {
+    let vfat = VFat::from(Cursor::new(resource_data!("mock1.fat32.img"))).expect("mount image");
+    let files: Vec<(String, Vec<u8>)> = (0..4)
+        .map(|i| {
+            let name = format!("/reader{}.bin", i);
+            let data: Vec<u8> = (0..20000).map(|j| ((i * 31 + j) % 251) as u8).collect();
+            vfat.create_file(&name).expect("create file")
+                .write_all(&data).expect("write file");
+            (name, data)
+        })
+        .collect();
+
+    {
+        let first = vfat.borrow();
+        let second = vfat.borrow();
+        assert_eq!(first.cluster_size(), second.cluster_size(), "shared borrows coexist");
+    }
+
+    let threads: Vec<_> = files.into_iter()
+        .map(|(name, data)| {
+            let vfat = vfat.clone();
+            ::std::thread::spawn(move || {
+                for _ in 0..8 {
+                    assert!(read_file(&vfat, &name) == data, "concurrent read of {}", name);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("reader thread");
+    }
+}
+
 #[test]
 fn shared_fs_is_sync_send_static() {
     fn f<T: Sync + Send + 'static>() {  }
@@ -52,6 +52,57 @@ pub trait BlockDevice: Send {
     /// error of `UnexpectedEof` if the length of `buf` is less than
     /// `self.sector_size()`.
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize>;
+
+    /// Read `count` sectors beginning at sector `start` into `buf`.
+    ///
+    /// `count * self.sector_size()` or `buf.len()` bytes, whichever is less,
+    /// are read into `buf`. The number of bytes read is returned. The default
+    /// implementation calls `read_sector()` once per sector; implementors that
+    /// can read a run of sectors in a single request should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or reading from `self` fails.
+    fn read_sectors(&mut self, start: u64, count: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let len = ::std::cmp::min(count as usize * sector_size, buf.len());
+
+        let mut read = 0;
+        for (i, chunk) in buf[..len].chunks_mut(sector_size).enumerate() {
+            let bytes = self.read_sector(start + i as u64, chunk)?;
+            read += bytes;
+            if bytes < chunk.len() {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Overwrites `count` sectors beginning at sector `start` with the contents
+    /// of `buf`.
+    ///
+    /// `count * self.sector_size()` or `buf.len()` bytes, whichever is less,
+    /// are written. The number of bytes written is returned. The default
+    /// implementation calls `write_sector()` once per sector; implementors that
+    /// can write a run of sectors in a single request should override it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking or writing to `self` fails.
+    fn write_sectors(&mut self, start: u64, count: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector_size = self.sector_size() as usize;
+        let len = ::std::cmp::min(count as usize * sector_size, buf.len());
+
+        let mut written = 0;
+        for (i, chunk) in buf[..len].chunks(sector_size).enumerate() {
+            let bytes = self.write_sector(start + i as u64, chunk)?;
+            written += bytes;
+            if bytes < chunk.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
 }
 
 impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
@@ -62,6 +113,14 @@ impl<'a, T: BlockDevice> BlockDevice for &'a mut T {
     fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
         (*self).write_sector(n, buf)
     }
+
+    fn read_sectors(&mut self, start: u64, count: u64, buf: &mut [u8]) -> io::Result<usize> {
+        (*self).read_sectors(start, count, buf)
+    }
+
+    fn write_sectors(&mut self, start: u64, count: u64, buf: &[u8]) -> io::Result<usize> {
+        (*self).write_sectors(start, count, buf)
+    }
 }
 
 macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
@@ -83,6 +142,22 @@ macro impl_for_read_write_seek($(<$($gen:tt),*>)* $T:path) {
             self.write_all(&buf[..to_write])?;
             Ok(to_write)
         }
+
+        fn read_sectors(&mut self, start: u64, count: u64, buf: &mut [u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_read = ::std::cmp::min((count * sector_size) as usize, buf.len());
+            self.seek(io::SeekFrom::Start(start * sector_size))?;
+            self.read_exact(&mut buf[..to_read])?;
+            Ok(to_read)
+        }
+
+        fn write_sectors(&mut self, start: u64, count: u64, buf: &[u8]) -> io::Result<usize> {
+            let sector_size = self.sector_size();
+            let to_write = ::std::cmp::min((count * sector_size) as usize, buf.len());
+            self.seek(io::SeekFrom::Start(start * sector_size))?;
+            self.write_all(&buf[..to_write])?;
+            Ok(to_write)
+        }
     }
 }
 
@@ -10,6 +10,11 @@ pub trait File: io::Read + io::Write + io::Seek + Sized {
 
     /// Returns the size of the file in bytes.
     fn size(&self) -> u64;
+
+    /// Truncates or extends the file to `size` bytes. Bytes added by extending
+    /// the file read as zero. If the current position is past the new end of
+    /// the file, it is moved to the end of the file.
+    fn set_len(&mut self, size: u64) -> io::Result<()>;
 }
 
 /// Trait implemented by directories in a file system.
@@ -179,8 +184,8 @@ pub trait FileSystem: Sized {
     ///
     /// If there is no entry at `path`, an error kind of `NotFound` is returned.
     ///
-    /// If the entry at `path` is a directory and `children` is `false`, an
-    /// error kind of `Other` is returned.
+    /// If the entry at `path` is a non-empty directory and `children` is
+    /// `false`, an error kind of `Other` is returned.
     ///
     /// All other error values are implementation defined.
     fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()>;
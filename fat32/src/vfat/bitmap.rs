@@ -0,0 +1,130 @@
+use vfat::Cluster;
+
+/// An in-memory map of the free and used data clusters of a file system.
+#[derive(Debug)]
+pub struct ClusterBitmap {
+    /// One bit per data cluster, set when the cluster is in use.
+    used: Vec<u64>,
+    len: u32,
+    free: u32,
+}
+
+impl ClusterBitmap {
+    /// Returns a bitmap of `len` data clusters, all of which are free.
+    pub fn new(len: u32) -> ClusterBitmap {
+        ClusterBitmap {
+            used: vec![0; (len as usize + 63) / 64],
+            len: len,
+            free: len,
+        }
+    }
+
+    /// The number of free clusters.
+    pub fn free_count(&self) -> u32 {
+        self.free
+    }
+
+    /// Returns the bit index of `cluster`, or `None` if `cluster` is not a
+    /// data cluster of the file system.
+    fn index(&self, cluster: Cluster) -> Option<u32> {
+        match cluster.id() {
+            id if id >= 2 && id - 2 < self.len => Some(id - 2),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if `cluster` is free. Clusters outside of the data
+    /// region are never free.
+    pub fn is_free(&self, cluster: Cluster) -> bool {
+        match self.index(cluster) {
+            Some(i) => self.used[(i / 64) as usize] & (1 << (i % 64)) == 0,
+            None => false,
+        }
+    }
+
+    /// Marks `cluster` as used or free.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cluster` is outside of the data region.
+    pub fn set_used(&mut self, cluster: Cluster, used: bool) {
+        let i = self.index(cluster).expect("cluster out of bounds");
+        if self.is_free(cluster) == !used {
+            return;
+        }
+
+        if used {
+            self.used[(i / 64) as usize] |= 1 << (i % 64);
+            self.free -= 1;
+        } else {
+            self.used[(i / 64) as usize] &= !(1 << (i % 64));
+            self.free += 1;
+        }
+    }
+
+    /// Returns the index of the first free cluster in `start..end`.
+    fn next_free(&self, start: u32, end: u32) -> Option<u32> {
+        let mut i = start;
+        while i < end {
+            let word = self.used[(i / 64) as usize];
+            if i % 64 == 0 && word == !0 {
+                i += 64;
+            } else if word & (1 << (i % 64)) == 0 {
+                return Some(i);
+            } else {
+                i += 1;
+            }
+        }
+        None
+    }
+
+    /// Returns the first free cluster at or after `hint`, wrapping around to
+    /// the start of the bitmap. Returns `None` if every cluster is used.
+    pub fn find_free(&self, hint: Cluster) -> Option<Cluster> {
+        let hint = hint.id().saturating_sub(2) % self.len.max(1);
+        self.next_free(hint, self.len)
+            .or_else(|| self.next_free(0, hint))
+            .map(|i| Cluster::from(i + 2))
+    }
+
+    /// Returns `n` free clusters, searching from `hint` and wrapping around to
+    /// the start of the bitmap. The first run of `n` contiguous free clusters
+    /// is preferred; if there is none, the first `n` free clusters are
+    /// returned. Returns `None` if fewer than `n` clusters are free.
+    pub fn find_free_run(&self, n: u32, hint: Cluster) -> Option<Vec<Cluster>> {
+        if n > self.free {
+            return None;
+        } else if n == 0 {
+            return Some(vec![]);
+        }
+
+        let hint = hint.id().saturating_sub(2) % self.len.max(1);
+        for &start in [hint, 0].iter() {
+            let mut run_len = 0;
+            let mut i = start;
+            while let Some(free) = self.next_free(i, self.len) {
+                run_len = if run_len > 0 && free == i { run_len + 1 } else { 1 };
+                if run_len == n {
+                    let run_start = free + 1 - n;
+                    return Some((run_start..(free + 1)).map(|i| Cluster::from(i + 2)).collect());
+                }
+                i = free + 1;
+            }
+        }
+
+        let mut clusters = Vec::with_capacity(n as usize);
+        for &(start, end) in [(hint, self.len), (0, hint)].iter() {
+            let mut i = start;
+            while clusters.len() < n as usize {
+                match self.next_free(i, end) {
+                    Some(free) => {
+                        clusters.push(Cluster::from(free + 2));
+                        i = free + 1;
+                    }
+                    None => break,
+                }
+            }
+        }
+        Some(clusters)
+    }
+}
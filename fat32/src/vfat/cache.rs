@@ -1,15 +1,16 @@
 use std::{io, fmt};
-use std::collections::HashMap;
-use std::collections::hash_map::Entry;
+use std::collections::{HashMap, BTreeMap};
 
 use std::io::Write;
 
 use traits::BlockDevice;
+use vfat::{MountOptions, Stats};
 
 #[derive(Debug)]
 struct CacheEntry {
     data: Vec<u8>,
-    dirty: bool
+    dirty: bool,
+    last_used: u64
 }
 
 #[derive(Debug)]
@@ -23,7 +24,12 @@ pub struct Partition {
 pub struct CachedDevice {
     device: Box<BlockDevice>,
     cache: HashMap<u64, CacheEntry>,
-    partition: Partition
+    partition: Partition,
+    capacity: Option<usize>,
+    /// Maps the `last_used` tick of every cached sector to the sector.
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    stats: Stats
 }
 
 impl CachedDevice {
@@ -43,21 +49,57 @@ impl CachedDevice {
     /// `partition.sector_size` must be an integer multiple of
     /// `device.sector_size()`.
     ///
+    /// The cache's capacity is that of `MountOptions::default()`.
+    ///
     /// # Panics
     ///
     /// Panics if the partition's sector size is < the device's sector size.
     pub fn new<T>(device: T, partition: Partition) -> CachedDevice
         where T: BlockDevice + 'static
+    {
+        CachedDevice::with_capacity(device, partition, MountOptions::default().cache_capacity)
+    }
+
+    /// Creates a new `CachedDevice` like `new()` that holds at most `capacity`
+    /// sectors in memory. When the cache is full, the least recently used
+    /// sector is evicted to make room, writing it back to `device` first if it
+    /// is dirty. A `capacity` of `None` places no bound on the cache. A
+    /// capacity of `0` is treated as `1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the partition's sector size is < the device's sector size.
+    pub fn with_capacity<T>(
+        device: T,
+        partition: Partition,
+        capacity: Option<usize>
+    ) -> CachedDevice
+        where T: BlockDevice + 'static
     {
         assert!(partition.sector_size >= device.sector_size());
 
         CachedDevice {
             device: Box::new(device),
             cache: HashMap::new(),
-            partition: partition
+            partition: partition,
+            capacity: capacity.map(|capacity| ::std::cmp::max(capacity, 1)),
+            lru: BTreeMap::new(),
+            clock: 0,
+            stats: Stats::default()
         }
     }
 
+    /// Returns the cache and I/O counters of this device. The `fat_lookups`
+    /// counter is always zero.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Resets every counter returned by `stats()` to zero.
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats::default();
+    }
+
     /// Maps a user's request for a sector `virt` to the physical sector and
     /// number of physical sectors required to access `virt`.
     fn virtual_to_physical(&self, virt: u64) -> (u64, u64) {
@@ -74,6 +116,65 @@ impl CachedDevice {
         }
     }
 
+    /// Writes every dirty cached sector back to the underlying device and marks
+    /// it clean. A logical sector is written as each of the physical sectors
+    /// it spans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the device fails. Sectors that were not
+    /// successfully written remain dirty.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<u64> = self.cache.iter()
+            .filter(|&(_, entry)| entry.dirty)
+            .map(|(&sector, _)| sector)
+            .collect();
+        dirty.sort();
+
+        for sector in dirty {
+            self.write_back(sector)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the cached sector `sector` back to the device if it is dirty and
+    /// marks it clean.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the device fails. The sector remains
+    /// dirty.
+    pub fn write_back(&mut self, sector: u64) -> io::Result<()> {
+        let (phys_sector, count) = self.virtual_to_physical(sector + self.partition.start);
+        let entry = match self.cache.get_mut(&sector) {
+            Some(entry) => entry,
+            None => return Ok(())
+        };
+
+        if entry.dirty {
+            self.device.write_sectors(phys_sector, count, &entry.data)?;
+            entry.dirty = false;
+            self.stats.write_backs += 1;
+            self.stats.sectors_written += count;
+        }
+        Ok(())
+    }
+
+    /// Evicts the least recently used sector from the cache, writing it back
+    /// first if it is dirty. The sector remains cached if the write fails.
+    fn evict(&mut self) -> io::Result<()> {
+        let (last_used, sector) = match self.lru.iter().next() {
+            Some((&last_used, &sector)) => (last_used, sector),
+            None => return Ok(())
+        };
+
+        self.write_back(sector)?;
+        self.lru.remove(&last_used);
+        self.cache.remove(&sector);
+        self.stats.evictions += 1;
+        Ok(())
+    }
+
     /// Returns a mutable reference to the cached sector `sector`. If the sector
     /// is not already cached, the sector is first read from the disk.
     ///
@@ -100,27 +201,155 @@ impl CachedDevice {
     }
 
 
-    fn get_internal(&mut self, sector: u64, dirty: bool) -> io::Result<&mut [u8]> {
-        let (phys_sector, count) = { self.virtual_to_physical(sector + self.partition.start) };
-        // println!("logical_sector={:} phys_sector_offset={:} count={:}", sector, phys_sector, count);
-        let entry = self.cache.entry(sector);
-        match entry {
-            Entry::Occupied(oe) => {
-                let mut cache_entry = oe.into_mut();
-                if dirty {
-                    cache_entry.dirty = true;
-                }
-                return Ok(&mut cache_entry.data);
-            },
-            Entry::Vacant(ve) => {
-                let mut data = Vec::with_capacity((count * self.device.sector_size()) as usize);
-                for i in phys_sector..(phys_sector + count) {
-                    self.device.read_all_sector(i, &mut data)?;
+    /// Returns `true` if the sector `sector` is in the cache.
+    pub fn contains(&self, sector: u64) -> bool {
+        self.cache.contains_key(&sector)
+    }
+
+    /// Loads logical sectors `start..(start + count)` into the cache. Each run
+    /// of sectors in the range that is not already cached is read from the
+    /// device with a single `read_sectors()` request. If the cache is bounded,
+    /// at most `capacity` sectors are loaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sectors from the disk.
+    pub fn prefetch(&mut self, start: u64, count: u64) -> io::Result<()> {
+        let count = match self.capacity {
+            Some(capacity) => ::std::cmp::min(count, capacity as u64),
+            None => count
+        };
+
+        let end = start + count;
+        let mut sector = start;
+        while sector < end {
+            if self.cache.contains_key(&sector) {
+                sector += 1;
+                continue;
+            }
+
+            let mut run_end = sector + 1;
+            while run_end < end && !self.cache.contains_key(&run_end) {
+                run_end += 1;
+            }
+            self.load(sector, run_end - sector)?;
+            sector = run_end;
+        }
+        Ok(())
+    }
+
+    /// Copies `buf.len()` bytes beginning at byte `offset` of logical sector
+    /// `start`, continuing into the sectors that follow it, into `buf`. Runs of
+    /// sectors that are not cached are first loaded with `prefetch()`. Returns
+    /// the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sectors from the disk.
+    pub fn read(&mut self, start: u64, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        let sector_size = self.partition.sector_size as usize;
+        let end = start + ((offset + buf.len() + sector_size - 1) / sector_size) as u64;
+
+        let mut bytes_read = 0;
+        let mut offset = offset;
+        for sector in start..end {
+            if self.contains(sector) {
+                self.stats.cache_hits += 1;
+            } else {
+                self.prefetch(sector, end - sector)?;
+            }
+            let data = self.touch(sector, false);
+            let len = ::std::cmp::min(data.len() - offset, buf.len() - bytes_read);
+            buf[bytes_read..(bytes_read + len)].copy_from_slice(&data[offset..(offset + len)]);
+            bytes_read += len;
+            offset = 0;
+        }
+        Ok(bytes_read)
+    }
+
+    /// Reads logical sectors `start..(start + count)` straight from the device
+    /// into `buf` without inserting them into the cache. Sectors that are
+    /// already cached are copied from the cache instead so that modifications
+    /// not yet written back are observed. Returns the number of bytes read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is an error reading the sectors from the disk
+    /// or if `buf` is smaller than `count` sectors.
+    pub fn read_direct(&mut self, start: u64, count: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (phys_sector, factor) = self.virtual_to_physical(start + self.partition.start);
+        let sector_size = (factor * self.device.sector_size()) as usize;
+        let len = sector_size * count as usize;
+        if buf.len() < len {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small"));
+        }
+
+        if self.device.read_sectors(phys_sector, factor * count, &mut buf[..len])? < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short sector read"));
+        }
+        self.stats.sectors_read += factor * count;
+
+        for i in 0..(count as usize) {
+            if let Some(entry) = self.cache.get(&(start + i as u64)) {
+                buf[(i * sector_size)..((i + 1) * sector_size)].copy_from_slice(&entry.data);
+            }
+        }
+        Ok(len)
+    }
+
+    /// Reads the `count` uncached logical sectors beginning at `start` from
+    /// the device in one request and inserts them into the cache.
+    fn load(&mut self, start: u64, count: u64) -> io::Result<()> {
+        let (phys_sector, factor) = self.virtual_to_physical(start + self.partition.start);
+        let sector_size = (factor * self.device.sector_size()) as usize;
+        let mut data = vec![0; sector_size * count as usize];
+        if self.device.read_sectors(phys_sector, factor * count, &mut data)? < data.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short sector read"));
+        }
+        self.stats.sectors_read += factor * count;
+        self.stats.cache_misses += count;
+
+        for (i, chunk) in data.chunks(sector_size).enumerate() {
+            if let Some(capacity) = self.capacity {
+                while self.cache.len() >= capacity {
+                    self.evict()?;
                 }
-                let mut cache_entry = ve.insert(CacheEntry { data: data, dirty: dirty });
-                return Ok(&mut cache_entry.data);
             }
+
+            self.clock += 1;
+            let sector = start + i as u64;
+            self.lru.insert(self.clock, sector);
+            self.cache.insert(sector, CacheEntry { data: chunk.to_vec(), dirty: false, last_used: self.clock });
+        }
+        Ok(())
+    }
+
+    fn get_internal(&mut self, sector: u64, dirty: bool) -> io::Result<&mut [u8]> {
+        if self.cache.contains_key(&sector) {
+            self.stats.cache_hits += 1;
+        } else {
+            self.load(sector, 1)?;
+        }
+        Ok(self.touch(sector, dirty))
+    }
+
+    /// Marks the cached sector `sector` as the most recently used, and dirty if
+    /// `dirty` is `true`, and returns its data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector` is not cached.
+    fn touch(&mut self, sector: u64, dirty: bool) -> &mut [u8] {
+        self.clock += 1;
+        let now = self.clock;
+        let cache_entry = self.cache.get_mut(&sector).expect("sector is cached");
+        if dirty {
+            cache_entry.dirty = true;
         }
+        self.lru.remove(&cache_entry.last_used);
+        self.lru.insert(now, sector);
+        cache_entry.last_used = now;
+        &mut cache_entry.data
     }
 }
 
@@ -148,8 +377,13 @@ impl BlockDevice for CachedDevice {
     }
 }
 
-// FIXME: Implement `BlockDevice` for `CacheDevice`. The `read_sector` and
-// `write_sector` methods should only read/write from/to cached sectors.
+impl Drop for CachedDevice {
+    /// Writes back dirty sectors. Errors, such as those from removed media,
+    /// are ignored; call `flush()` to handle them.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
 
 impl fmt::Debug for CachedDevice {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -157,6 +391,7 @@ impl fmt::Debug for CachedDevice {
             .field("device", &"<block device>")
             .field("cache", &self.cache.keys())
             .field("partition", &self.partition)
+            .field("capacity", &self.capacity)
             .finish()
     }
 }
@@ -1,6 +1,8 @@
+use std::cmp::min;
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::borrow::{BorrowMut};
 use std::io;
+use std::mem::size_of;
 use std::vec::IntoIter;
 use traits;
 use util::VecExt;
@@ -12,7 +14,19 @@ pub struct Dir {
     pub fs: Shared<VFat>,
     pub start_cluster: Cluster,
     pub name: String,
-    pub metadata: Metadata
+    pub metadata: Metadata,
+    pub(crate) dirent: Option<DirentLoc>,
+}
+
+/// The location of an entry's 32-byte slots within its parent directory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DirentLoc {
+    /// The first cluster of the parent directory.
+    pub dir: Cluster,
+    /// The index of the regular entry's slot in the parent directory.
+    pub index: usize,
+    /// The number of LFN slots immediately preceding the regular entry.
+    pub lfn_count: usize,
 }
 
 #[repr(C, packed)]
@@ -38,6 +52,26 @@ enum RegularSeq {
 }
 
 impl VFatRegularDirEntry {
+    /// Returns a new entry with an empty name, attributes `attribs`, and all
+    /// timestamps set to `time`.
+    pub(crate) fn new(attribs: Attributes, start_cluster: Cluster, time: Timestamp) -> VFatRegularDirEntry {
+        let mut entry = VFatRegularDirEntry {
+            name: [0x20; 8],
+            ext: [0x20; 3],
+            attribs: attribs.0,
+            _reserved: 0,
+            creation_decisecs: 0,
+            created: time,
+            accessed: time.date,
+            hi_cluster_part: 0,
+            modified: time,
+            lo_cluster_part: 0,
+            size: 0
+        };
+        entry.set_start_cluster(start_cluster);
+        entry
+    }
+
     fn seq(&self) -> RegularSeq {
         match self.name[0] {
             0xE5 => RegularSeq::Deleted,
@@ -101,14 +135,44 @@ impl VFatRegularDirEntry {
         Cluster::from(id)
     }
 
-    fn into_entry(self, lfn_ent: LfnEnt, fs: Shared<VFat>) -> Entry {
+    fn short_name(&self) -> [u8; 11] {
+        let mut short_name = [0; 11];
+        short_name[..8].copy_from_slice(&self.name);
+        short_name[8..].copy_from_slice(&self.ext);
+        short_name
+    }
+
+    pub(crate) fn set_short_name(&mut self, short_name: &[u8; 11]) {
+        self.name.copy_from_slice(&short_name[..8]);
+        self.ext.copy_from_slice(&short_name[8..]);
+    }
+
+    pub(crate) fn set_start_cluster(&mut self, cluster: Cluster) {
+        self.hi_cluster_part = (cluster.id() >> 16) as u16;
+        self.lo_cluster_part = cluster.id() as u16;
+    }
+
+    pub(crate) fn set_size(&mut self, size: u32) {
+        self.size = size;
+    }
+
+    pub(crate) fn set_modified(&mut self, modified: Timestamp) {
+        self.modified = modified;
+        self.accessed = modified.date;
+    }
+
+    fn into_entry(self, lfn_ent: LfnEnt, fs: Shared<VFat>, loc: DirentLoc) -> Entry {
         let name = self.name(lfn_ent);
         let metadata = self.metadata();
-        let start_cluster = self.start_cluster();
+        let mut start_cluster = self.start_cluster();
         if self.attribs & 0x10 == 0x10 { // its a dir
-            Entry::Dir(Dir { fs, start_cluster, name, metadata, })
+            if start_cluster.id() == 0 {
+                // a `..` entry referring to the root directory
+                start_cluster = fs.borrow().root_cluster();
+            }
+            Entry::Dir(Dir { fs, start_cluster, name, metadata, dirent: Some(loc) })
         } else {
-            Entry::File(File::new(fs, start_cluster, name, metadata, self.size))
+            Entry::File(File::new(fs, start_cluster, name, metadata, self.size, Some(loc)))
         }
     }
 }
@@ -146,6 +210,27 @@ impl VFatLfnDirEntry {
         }
     }
 
+    /// Returns the LFN entry with sequence number `seq` (1-indexed) for the
+    /// UTF-16 name `name`. `name` must already be terminated and padded to a
+    /// multiple of 13 characters.
+    fn new(name: &[u16], seq: u8, last: bool, checksum: u8) -> VFatLfnDirEntry {
+        let part = &name[((seq - 1) as usize * 13)..(seq as usize * 13)];
+        let (mut name_part_1, mut name_part_2, mut name_part_3) = ([0; 5], [0; 6], [0; 2]);
+        name_part_1.copy_from_slice(&part[..5]);
+        name_part_2.copy_from_slice(&part[5..11]);
+        name_part_3.copy_from_slice(&part[11..]);
+        VFatLfnDirEntry {
+            sequence_number: if last { seq | 0x40 } else { seq },
+            name_part_1,
+            attribs: 0x0F,
+            dtype: 0,
+            checksum,
+            name_part_2,
+            reserved_: 0,
+            name_part_3,
+        }
+    }
+
     fn extend_name(&self, mut name: Vec<u16>) -> Vec<u16> {
         let mut i = 0;
         let base = ((self.sequence_number & 0b11111) - 1) as usize * 13;
@@ -172,10 +257,18 @@ pub struct VFatUnknownDirEntry {
 }
 
 #[allow(dead_code)]
+#[derive(Copy, Clone)]
 pub union VFatDirEntry {
-    unknown: VFatUnknownDirEntry,
-    regular: VFatRegularDirEntry,
-    long_filename: VFatLfnDirEntry,
+    pub unknown: VFatUnknownDirEntry,
+    pub regular: VFatRegularDirEntry,
+    pub long_filename: VFatLfnDirEntry,
+}
+
+impl VFatDirEntry {
+    /// Marks the slot as deleted (free).
+    pub(crate) fn mark_deleted(&mut self) {
+        self.unknown.seq = 0xE5;
+    }
 }
 
 pub enum DirEntry<'a> {
@@ -207,6 +300,10 @@ impl Dir {
     pub fn find<P: AsRef<OsStr>>(&self, name: P) -> io::Result<Entry> {
         use traits::{Dir, Entry};
         if let Some(name_utf8) = name.as_ref().to_str() {
+            if self.fs.borrow().dir_index_enabled() {
+                return self.find_indexed(name_utf8);
+            }
+
             match self.entries()?.find(|ref x| x.name().eq_ignore_ascii_case(name_utf8)) {
                 Some(entry) => Ok(entry),
                 None => Err(io::Error::new(io::ErrorKind::NotFound, "file not found")),
@@ -215,12 +312,226 @@ impl Dir {
             Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid utf-8 in name"))
         }
     }
+
+    /// Finds the entry named `name` using the file system's index of `self`,
+    /// building the index first if it does not exist.
+    fn find_indexed(&self, name: &str) -> io::Result<Entry> {
+        use traits::{Dir, Entry};
+        let key = name.to_ascii_lowercase();
+        let indexed = self.fs.borrow().dir_index_lookup(self.start_cluster, &key);
+        let loc = match indexed {
+            Some(loc) => loc,
+            None => {
+                let mut index = HashMap::new();
+                for entry in self.entries()? {
+                    let loc = entry.dirent().expect("directory entry has a location");
+                    index.entry(entry.name().to_ascii_lowercase()).or_insert(loc);
+                }
+                let loc = index.get(&key).cloned();
+                self.fs.borrow_mut().set_dir_index(self.start_cluster, index);
+                loc
+            }
+        };
+
+        let found = match loc {
+            Some(loc) => DirIter::at(self.fs.clone(), self.start_cluster, loc.index - loc.lfn_count)?.next(),
+            None => None,
+        };
+        found.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "file not found"))
+    }
+
+    /// Returns `true` if `self` contains no entries other than `.` and `..`.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        use traits::{Dir, Entry};
+        Ok(self.entries()?.all(|e| e.name() == "." || e.name() == ".."))
+    }
+
+    /// Marks the slots of the entry at `loc` in `self` as deleted. Returns
+    /// the slots' previous contents, which can be passed to `restore()`.
+    pub(crate) fn remove(&self, loc: DirentLoc) -> io::Result<Vec<VFatDirEntry>> {
+        let mut fs = self.fs.borrow_mut();
+        fs.invalidate_dir_index(self.start_cluster);
+        let mut removed = Vec::with_capacity(loc.lfn_count + 1);
+        for index in (loc.index - loc.lfn_count)..(loc.index + 1) {
+            let slot = fs.dir_slot_mut(self.start_cluster, index)?;
+            removed.push(*slot);
+            slot.mark_deleted();
+        }
+        fs.write_pending()?;
+        Ok(removed)
+    }
+
+    /// Writes `slots`, as returned by `remove(loc)`, back to their location.
+    pub(crate) fn restore(&self, loc: DirentLoc, slots: &[VFatDirEntry]) -> io::Result<()> {
+        let mut fs = self.fs.borrow_mut();
+        fs.invalidate_dir_index(self.start_cluster);
+        for (i, slot) in slots.iter().enumerate() {
+            *fs.dir_slot_mut(self.start_cluster, loc.index - loc.lfn_count + i)? = *slot;
+        }
+        fs.write_pending()
+    }
+
+    /// Adds an entry named `name` to `self`. The entry's attributes, start
+    /// cluster, size, and timestamps are taken from `template`; its short name
+    /// is generated from `name`. Returns the location of the new entry.
+    ///
+    /// Free slots in the directory are reused when possible. Otherwise the
+    /// directory's chain is extended with zeroed clusters.
+    ///
+    /// # Errors
+    ///
+    /// If `name` is not a valid long file name, an error of `InvalidInput` is
+    /// returned. The caller is responsible for ensuring that no entry named
+    /// `name` already exists.
+    pub(crate) fn insert(&self, name: &str, mut template: VFatRegularDirEntry) -> io::Result<DirentLoc> {
+        if !valid_long_name(name) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"));
+        }
+
+        let mut fs = self.fs.borrow_mut();
+        let mut buf = Vec::new();
+        fs.read_dir(self.start_cluster, &mut buf)?;
+        let slots: Vec<VFatUnknownDirEntry> = unsafe { buf.cast() };
+
+        let existing: Vec<[u8; 11]> = slots.iter()
+            .take_while(|slot| slot.seq != 0)
+            .filter_map(|slot| match DirEntry::from(slot) {
+                DirEntry::Regular(r) if slot.seq != 0xE5 => Some(r.short_name()),
+                _ => None,
+            })
+            .collect();
+        let (short_name, needs_lfn) = short_name(name, &existing);
+        template.set_short_name(&short_name);
+
+        let mut lfn_name: Vec<u16> = name.encode_utf16().collect();
+        let lfn_count = if needs_lfn { (lfn_name.len() + 12) / 13 } else { 0 };
+        if lfn_name.len() < lfn_count * 13 {
+            lfn_name.push(0);
+        }
+        lfn_name.resize(lfn_count * 13, 0xFFFF);
+
+        // find a run of `lfn_count + 1` free slots, possibly past the end
+        let needed = lfn_count + 1;
+        let mut run = 0;
+        let mut start = None;
+        for (i, slot) in slots.iter().enumerate() {
+            run = if slot.seq == 0 || slot.seq == 0xE5 { run + 1 } else { 0 };
+            if run == needed {
+                start = Some(i + 1 - needed);
+                break;
+            }
+        }
+
+        let start = match start {
+            Some(start) => start,
+            None if fs.is_fixed_root(self.start_cluster) => {
+                return Err(io::Error::new(io::ErrorKind::Other, "root directory is full"));
+            }
+            None => {
+                let per_cluster = fs.cluster_size() / size_of::<VFatDirEntry>();
+                let mut last = *fs.chain(self.start_cluster)?.last().unwrap();
+                for _ in 0..((needed - run + per_cluster - 1) / per_cluster) {
+                    last = fs.alloc_cluster(Some(last))?;
+                    fs.zero_cluster(last)?;
+                }
+                slots.len() - run
+            }
+        };
+
+        fs.invalidate_dir_index(self.start_cluster);
+        let checksum = template.checksum();
+        for i in 0..lfn_count {
+            let seq = (lfn_count - i) as u8;
+            let lfn = VFatLfnDirEntry::new(&lfn_name, seq, i == 0, checksum);
+            *fs.dir_slot_mut(self.start_cluster, start + i)? = VFatDirEntry { long_filename: lfn };
+        }
+        let index = start + lfn_count;
+        *fs.dir_slot_mut(self.start_cluster, index)? = VFatDirEntry { regular: template };
+        fs.write_pending()?;
+        Ok(DirentLoc { dir: self.start_cluster, index, lfn_count })
+    }
+}
+
+/// Returns `true` if `name` may be used as a long file name.
+fn valid_long_name(name: &str) -> bool {
+    name.len() != 0
+        && name != "." && name != ".."
+        && name.encode_utf16().count() <= 255
+        && !name.chars().any(|c| c < ' ' || "\"*/:<>?\\|".contains(c))
+}
+
+/// Generates a unique 8.3 short name for the long name `name` that does not
+/// collide with any name in `existing`. Returns the short name and whether
+/// `name` must also be stored as a long file name.
+fn short_name(name: &str, existing: &[[u8; 11]]) -> ([u8; 11], bool) {
+    fn convert(part: &str, len: usize, lossy: &mut bool) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len);
+        for c in part.chars() {
+            if c == ' ' || c == '.' {
+                *lossy = true;
+                continue;
+            } else if out.len() == len {
+                *lossy = true;
+                break;
+            }
+            let c = c.to_ascii_uppercase();
+            if c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c) {
+                out.push(c as u8);
+            } else {
+                *lossy = true;
+                out.push(b'_');
+            }
+        }
+        out
+    }
+
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(i) => (&trimmed[..i], &trimmed[(i + 1)..]),
+        None => (trimmed, ""),
+    };
+
+    let mut lossy = trimmed.len() != name.len();
+    let mut base = convert(base, 8, &mut lossy);
+    let ext = convert(ext, 3, &mut lossy);
+    if base.is_empty() {
+        lossy = true;
+        base.push(b'_');
+    }
+
+    let mut short_name = [0x20; 11];
+    short_name[..base.len()].copy_from_slice(&base);
+    short_name[8..(8 + ext.len())].copy_from_slice(&ext);
+    let display = if ext.is_empty() {
+        String::from_utf8_lossy(&base).into_owned()
+    } else {
+        format!("{}.{}", String::from_utf8_lossy(&base), String::from_utf8_lossy(&ext))
+    };
+
+    if !lossy && !existing.contains(&short_name) {
+        return (short_name, display != name);
+    }
+
+    for n in 1..1000000 {
+        let tail = format!("~{}", n);
+        let keep = min(base.len(), 8 - tail.len());
+        let mut candidate = [0x20; 11];
+        candidate[..keep].copy_from_slice(&base[..keep]);
+        candidate[keep..(keep + tail.len())].copy_from_slice(tail.as_bytes());
+        candidate[8..].copy_from_slice(&short_name[8..]);
+        if !existing.contains(&candidate) {
+            return (candidate, true);
+        }
+    }
+    panic!("no unique short name available for '{}'", name)
 }
 
 pub struct DirIter {
+    start: Cluster,
     next: Option<Cluster>,
     fs: Shared<VFat>,
-    curr_iter: Option<IntoIter<VFatUnknownDirEntry>>
+    curr_iter: Option<IntoIter<VFatUnknownDirEntry>>,
+    index: usize,
 }
 
 #[derive(Debug)]
@@ -266,30 +577,94 @@ impl LfnEnt {
     }
 }
 
+impl DirIter {
+    /// Returns an iterator over the entries of the directory beginning at
+    /// `start` whose first entry begins at slot `slot`.
+    fn at(fs: Shared<VFat>, start: Cluster, slot: usize) -> io::Result<DirIter> {
+        let (cluster, offset) = {
+            let vfat = fs.borrow();
+            let per_cluster = vfat.dir_cluster_size(start) / size_of::<VFatDirEntry>();
+            let mut cluster = start;
+            for _ in 0..(slot / per_cluster) {
+                cluster = match vfat.fat_entry(cluster)?.status() {
+                    Status::Data(next) => next,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "directory entry past end of chain")),
+                };
+            }
+            (cluster, slot % per_cluster)
+        };
+
+        let mut iter = DirIter { start, next: None, fs, curr_iter: None, index: slot - offset };
+        iter.load(cluster);
+        if offset > 0 {
+            iter.curr_iter.as_mut().unwrap().nth(offset - 1);
+            iter.index += offset;
+        }
+        Ok(iter)
+    }
+
+    /// Reads the slots of directory cluster `cluster`, or of the whole fixed
+    /// root directory region, and sets up iteration over them.
+    fn load(&mut self, cluster: Cluster) {
+        let fs = self.fs.borrow();
+        let size = fs.dir_cluster_size(cluster);
+        let mut buf = Vec::with_capacity(size);
+        unsafe {
+            buf.set_len(size);
+        }
+
+        let bytes_read = fs.read_dir_cluster(cluster, &mut buf).expect("read of directory failed");
+        assert_eq!(bytes_read, buf.capacity());
+        let dirents : Vec<VFatUnknownDirEntry> = unsafe { buf.cast() };
+        self.curr_iter = Some(dirents.into_iter());
+        if fs.is_fixed_root(cluster) {
+            self.next = None;
+            return;
+        }
+
+        self.next = match fs.fat_entry(cluster).expect("directory cluster lookup failed").status() {
+            Status::Data(cluster) => Some(cluster),
+            Status::Eoc(_) => None,
+            Status::Reserved => panic!("directory chain has a reserved cluster"),
+            Status::Free => panic!("directory chain has a free cluster"),
+            Status::Bad => panic!("directory chain has bad sector(s)"),
+        };
+    }
+}
+
 impl Iterator for DirIter {
     type Item = Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
         let mut lfn_ent = LfnEnt::None;
+        let mut lfn_count = 0;
         loop {
             if self.curr_iter.is_some() {
                 let iter = &mut *self.curr_iter.as_mut().unwrap();
                 for entry in iter {
+                    let index = self.index;
+                    self.index += 1;
                     let dirent = DirEntry::from(&entry);
                     match dirent {
                         DirEntry::Regular(ref r) => {
                             match r.seq() {
-                                RegularSeq::Deleted => continue,
+                                RegularSeq::Deleted => { lfn_count = 0; continue },
                                 RegularSeq::EndOfDirectory => return None,
-                                RegularSeq::Valid => return Some(r.into_entry(lfn_ent, self.fs.clone())),
+                                RegularSeq::Valid => {
+                                    let loc = DirentLoc { dir: self.start, index, lfn_count };
+                                    return Some(r.into_entry(lfn_ent, self.fs.clone(), loc));
+                                }
                             };
                         },
                         DirEntry::Lfn(ref lfn) => {
                             let seq = lfn.seq();
                             match seq {
-                                LfnSeq::Deleted => continue,
+                                LfnSeq::Deleted => { lfn_count = 0; continue },
                                 LfnSeq::EndOfDirectory => return None,
-                                LfnSeq::Seq(_, _, _) => { lfn_ent = lfn_ent.next(seq, lfn) },
+                                LfnSeq::Seq(_, _, _) => {
+                                    lfn_count += 1;
+                                    lfn_ent = lfn_ent.next(seq, lfn)
+                                },
                             };
                         }
                     };
@@ -297,25 +672,10 @@ impl Iterator for DirIter {
             } 
 
             if let Some(cluster) = self.next {
-                let mut fs = self.fs.borrow_mut();
-                let mut buf = Vec::with_capacity(fs.bytes_per_sector as usize * fs.sectors_per_cluster as usize);
-                unsafe {
-                    buf.set_len(fs.bytes_per_sector as usize * fs.sectors_per_cluster as usize);
-                }
-
-                let bytes_read = fs.borrow_mut().read_cluster(cluster, 0, &mut buf).expect("read of directory failed");
-                assert_eq!(bytes_read, buf.capacity());
-                let dirents : Vec<VFatUnknownDirEntry> = unsafe { buf.cast() };
-                self.curr_iter = Some(dirents.into_iter());
-                self.next = match fs.fat_entry(cluster).expect("directory cluster lookup failed").status() {
-                    Status::Data(cluster) => Some(cluster),
-                    Status::Eoc(_) => None,
-                    Status::Reserved => panic!("directory chain has a reserved cluster"),
-                    Status::Free => panic!("directory chain has a free cluster"),
-                    Status::Bad => panic!("directory chain has bad sector(s)"),
-                };
+                self.load(cluster);
             } else {
-                panic!("read last cluster before end of directory");
+                // the directory is full and has no end-of-directory marker
+                return None;
             }
         }
     }
@@ -327,8 +687,10 @@ impl traits::Dir for Dir {
     fn entries(&self)-> io::Result<Self::Iter> {
         Ok(DirIter { 
             fs: self.fs.clone(),
+            start: self.start_cluster,
             next: Some(self.start_cluster),
             curr_iter: None,
+            index: 0,
         })
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use std::{fmt, mem, slice};
 
 use traits::BlockDevice;
-use vfat::Error;
+use vfat::{Error, FatType};
 
 #[repr(C, packed)]
 pub struct BiosParameterBlock {
@@ -37,8 +37,9 @@ pub struct BiosParameterBlock {
 }
 
 impl BiosParameterBlock {
-    /// Reads the FAT32 extended BIOS parameter block from sector `sector` of
-    /// device `device`.
+    /// Reads the BIOS parameter block from sector `sector` of device `device`.
+    /// The fields following `logical_sectors_large` are only meaningful for a
+    /// FAT32 volume.
     ///
     /// # Errors
     ///
@@ -63,6 +64,87 @@ impl BiosParameterBlock {
     }
 }
 
+impl BiosParameterBlock {
+    /// The number of sectors occupied by each FAT. FAT12 and FAT16 volumes
+    /// record this in a field that FAT32 volumes leave zero.
+    pub fn fat_sectors(&self) -> u32 {
+        match self.sectors_per_fat_obsolete {
+            0 => self.sectors_per_fat,
+            sectors => sectors as u32,
+        }
+    }
+
+    /// The total number of sectors in the volume.
+    pub fn total_sectors(&self) -> u64 {
+        match self.logical_sectors_small {
+            0 => self.logical_sectors_large as u64,
+            sectors => sectors as u64,
+        }
+    }
+
+    /// The number of sectors occupied by the fixed root directory region of
+    /// a FAT12 or FAT16 volume. Zero for a FAT32 volume.
+    pub fn root_dir_sectors(&self) -> u64 {
+        let sector_bytes = self.sector_bytes as u64;
+        (self.max_dirent_count as u64 * 32 + sector_bytes - 1) / sector_bytes
+    }
+
+    /// The first sector of the data region.
+    pub fn data_start_sector(&self) -> u64 {
+        self.reserved_sectors as u64
+            + self.fat_count as u64 * self.fat_sectors() as u64
+            + self.root_dir_sectors()
+    }
+
+    /// The number of data clusters in the volume.
+    pub fn cluster_count(&self) -> u32 {
+        let data_sectors = self.total_sectors().saturating_sub(self.data_start_sector());
+        (data_sectors / self.sectors_per_cluster as u64) as u32
+    }
+
+    /// The FAT type of the volume, determined by its cluster count.
+    pub fn fat_type(&self) -> FatType {
+        FatType::from_cluster_count(self.cluster_count())
+    }
+
+    /// Returns `true` if this is the boot sector of a FAT volume of any type:
+    /// it begins with a jump instruction and describes a sane geometry. A
+    /// FAT32 boot sector must also carry an extended boot signature. A master
+    /// boot record does not pass these checks.
+    pub fn is_fat(&self) -> bool {
+        let jump = self.bootcode_trampoline;
+        let valid_jump = (jump[0] == 0xEB && jump[2] == 0x90) || jump[0] == 0xE9;
+        let sector_bytes = self.sector_bytes;
+        let sectors_per_cluster = self.sectors_per_cluster;
+
+        let sane = valid_jump
+            && sector_bytes >= 512 && sector_bytes <= 4096 && sector_bytes.is_power_of_two()
+            && sectors_per_cluster != 0 && sectors_per_cluster.is_power_of_two()
+            && self.reserved_sectors != 0
+            && self.fat_count != 0
+            && self.fat_sectors() != 0
+            && self.total_sectors() > self.data_start_sector();
+        if !sane {
+            return false;
+        }
+
+        match self.fat_type() {
+            FatType::Fat32 => {
+                self.max_dirent_count == 0
+                    && self.sectors_per_fat_obsolete == 0
+                    && (self.signature == 0x28 || self.signature == 0x29)
+            }
+            FatType::Fat12 | FatType::Fat16 => self.max_dirent_count != 0,
+        }
+    }
+
+    /// Returns `true` if this is the boot sector of a FAT32 volume: `is_fat()`
+    /// holds and the volume's FAT type is FAT32.
+    pub fn is_fat32(&self) -> bool {
+        self.is_fat() && self.fat_type() == FatType::Fat32
+    }
+}
+
 impl fmt::Debug for BiosParameterBlock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("BiosParameterBlock")
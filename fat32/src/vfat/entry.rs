@@ -1,5 +1,5 @@
 use traits;
-use vfat::{File, Dir, Metadata};
+use vfat::{File, Dir, Metadata, Cluster, DirentLoc};
 
 // TODO: You may need to change this definition.
 #[derive(Debug)]
@@ -8,7 +8,24 @@ pub enum Entry {
     Dir(Dir)
 }
 
-// TODO: Implement any useful helper methods on `Entry`.
+impl Entry {
+    /// The first cluster of the entry's data. Zero for an empty file.
+    pub(crate) fn start_cluster(&self) -> Cluster {
+        match *self {
+            Entry::File(ref f) => f.start_cluster(),
+            Entry::Dir(ref d) => d.start_cluster,
+        }
+    }
+
+    /// The location of the entry in its parent directory, or `None` for the
+    /// root directory.
+    pub(crate) fn dirent(&self) -> Option<DirentLoc> {
+        match *self {
+            Entry::File(ref f) => f.dirent(),
+            Entry::Dir(ref d) => d.dirent,
+        }
+    }
+}
 
 // FIXME: Implement `traits::Entry` for `Entry`.
 
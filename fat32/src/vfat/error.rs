@@ -1,13 +1,17 @@
 use std::io;
 
 use mbr;
+use gpt;
 
 #[derive(Debug)]
 pub enum Error {
     Mbr(mbr::Error),
+    Gpt(gpt::Error),
     Io(io::Error),
     BadSignature,
-    NotFound
+    NotFound,
+    /// Partition `.0` (0-indexed) is an empty partition table entry.
+    EmptyPartition(usize)
 }
 
 impl From<mbr::Error> for Error {
@@ -16,6 +20,12 @@ impl From<mbr::Error> for Error {
     }
 }
 
+impl From<gpt::Error> for Error {
+    fn from(error: gpt::Error) -> Error {
+        Error::Gpt(error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Error {
         Error::Io(error)
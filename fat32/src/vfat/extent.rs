@@ -0,0 +1,64 @@
+use vfat::Cluster;
+
+/// A run of `len` physically contiguous clusters beginning at `start`. The
+/// run begins at cluster number `index` of its chain.
+#[derive(Debug, Copy, Clone)]
+struct Extent {
+    index: usize,
+    start: Cluster,
+    len: usize,
+}
+
+/// A run-length encoding of a cluster chain.
+///
+/// Looking up the cluster at a given position in the chain only requires a
+/// binary search over the runs rather than a walk of the FAT.
+#[derive(Debug, Default)]
+pub struct Extents(Vec<Extent>);
+
+impl Extents {
+    /// Returns the extents of the chain made up of `chain`, in order.
+    pub fn from_chain(chain: &[Cluster]) -> Extents {
+        let mut extents = Extents::default();
+        for &cluster in chain {
+            extents.push(cluster);
+        }
+        extents
+    }
+
+    /// Appends `cluster` to the end of the chain.
+    pub fn push(&mut self, cluster: Cluster) {
+        if let Some(last) = self.0.last_mut() {
+            if last.start.id() as u64 + last.len as u64 == cluster.id() as u64 {
+                last.len += 1;
+                return;
+            }
+        }
+
+        let index = self.len();
+        self.0.push(Extent { index: index, start: cluster, len: 1 });
+    }
+
+    /// The number of clusters in the chain.
+    pub fn len(&self) -> usize {
+        self.0.last().map(|last| last.index + last.len).unwrap_or(0)
+    }
+
+    /// Returns the cluster at position `index` in the chain along with the
+    /// number of clusters, including that one, that follow it contiguously
+    /// on disk. Returns `None` if the chain is not that long.
+    pub fn find(&self, index: usize) -> Option<(Cluster, usize)> {
+        let i = match self.0.binary_search_by_key(&index, |extent| extent.index) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+
+        let extent = &self.0[i];
+        let offset = index - extent.index;
+        if offset >= extent.len {
+            return None;
+        }
+        Some((Cluster::from(extent.start.id() + offset as u32), extent.len - offset))
+    }
+}
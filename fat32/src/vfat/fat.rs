@@ -19,10 +19,109 @@ pub enum Status {
     Eoc(u32)
 }
 
+impl Status {
+    /// Returns the raw 28-bit FAT entry value that encodes `self`.
+    pub fn raw(&self) -> u32 {
+        match *self {
+            Free => 0,
+            Reserved => 0xFFFFFF0,
+            Data(cluster) => cluster.id(),
+            Bad => 0xFFFFFF7,
+            Eoc(id) => id,
+        }
+    }
+}
+
+/// The FAT variant of a file system, which determines the width of its FAT
+/// entries.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// Returns the FAT type of a file system with `cluster_count` data
+    /// clusters. The cluster count alone determines the type.
+    pub fn from_cluster_count(cluster_count: u32) -> FatType {
+        if cluster_count < 4085 {
+            FatType::Fat12
+        } else if cluster_count < 65525 {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+
+    /// Returns the byte offset of the entry for cluster `id` from the start
+    /// of a FAT.
+    pub(crate) fn entry_offset(self, id: u32) -> u64 {
+        let id = id as u64;
+        match self {
+            FatType::Fat12 => id + id / 2,
+            FatType::Fat16 => id * 2,
+            FatType::Fat32 => id * 4,
+        }
+    }
+
+    /// The number of bytes that hold a FAT entry. A FAT12 entry occupies the
+    /// low or high 12 bits of its two bytes.
+    pub(crate) fn entry_bytes(self) -> usize {
+        match self {
+            FatType::Fat12 | FatType::Fat16 => 2,
+            FatType::Fat32 => 4,
+        }
+    }
+
+    /// Returns the value of the entry for cluster `id` held in the little
+    /// endian `bytes`, including the upper four reserved bits of a FAT32
+    /// entry.
+    pub(crate) fn decode(self, id: u32, bytes: &[u8]) -> u32 {
+        let raw = bytes[..self.entry_bytes()].iter().rev()
+            .fold(0, |raw, &byte| raw << 8 | byte as u32);
+        match self {
+            FatType::Fat12 if id % 2 == 1 => raw >> 4,
+            FatType::Fat12 => raw & 0xFFF,
+            FatType::Fat16 | FatType::Fat32 => raw,
+        }
+    }
+
+    /// Stores `status` as the entry for cluster `id` in the little endian
+    /// `bytes`, preserving the bits of `bytes` that do not belong to the
+    /// entry: the upper four reserved bits of a FAT32 entry and the half byte
+    /// of a neighbouring FAT12 entry.
+    pub(crate) fn encode(self, id: u32, status: &Status, bytes: &mut [u8]) {
+        let len = self.entry_bytes();
+        let old = bytes[..len].iter().rev().fold(0, |raw, &byte| raw << 8 | byte as u32);
+        let new = match self {
+            FatType::Fat12 if id % 2 == 1 => (old & 0x000F) | (status.raw() & 0xFFF) << 4,
+            FatType::Fat12 => (old & 0xF000) | (status.raw() & 0xFFF),
+            FatType::Fat16 => status.raw() & 0xFFFF,
+            FatType::Fat32 => (old & 0xF0000000) | status.raw(),
+        };
+        for (i, byte) in bytes[..len].iter_mut().enumerate() {
+            *byte = (new >> (i * 8)) as u8;
+        }
+    }
+}
+
 #[repr(C, packed)]
+#[derive(Copy, Clone)]
 pub struct FatEntry(u32);
 
 impl FatEntry {
+    /// Returns the entry whose value in a FAT of type `fat_type` is `value`.
+    /// FAT12 and FAT16 reserved, bad, and end of chain values are widened to
+    /// their FAT32 equivalents.
+    pub fn new(fat_type: FatType, value: u32) -> FatEntry {
+        match fat_type {
+            FatType::Fat12 if value >= 0xFF0 => FatEntry(0xFFFF000 | value),
+            FatType::Fat16 if value >= 0xFFF0 => FatEntry(0xFFF0000 | value),
+            _ => FatEntry(value),
+        }
+    }
+
     /// Returns the `Status` of the FAT entry `self`.
     pub fn status(&self) -> Status {
         let cluster = Cluster::from(self.0);
@@ -35,6 +134,13 @@ impl FatEntry {
             _ => unreachable!(),
         }
     }
+
+    /// Returns the raw value of the entry, including the upper four reserved
+    /// bits of a FAT32 entry. FAT12 and FAT16 values are widened as by
+    /// `new()`.
+    pub fn value(&self) -> u32 {
+        self.0
+    }
 }
 
 impl fmt::Debug for FatEntry {
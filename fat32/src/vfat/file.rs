@@ -1,30 +1,314 @@
 use std::cmp::{min};
-use std::io::{self, SeekFrom};
+use std::io::{self, SeekFrom, Seek, Write};
 
 use traits;
-use vfat::{VFat, Shared, Cluster, Metadata, Status};
+use vfat::{VFat, Shared, Cluster, Metadata, Status, Timestamp, DirentLoc, Extents};
+
+/// The parts of a file that change as it is written: its cluster chain and
+/// size. These are shared by the file's cursor-based and positional accesses
+/// and, through the file system's table of open files, by every handle to the
+/// file.
+#[derive(Debug)]
+pub(crate) struct Chain {
+    /// The first cluster of the file. Zero for an empty file.
+    start: Cluster,
+    size: u32,
+    /// The file's cluster chain, built the first time it is needed.
+    extents: Option<Extents>,
+}
+
+impl Chain {
+    /// Returns the chain of a file of `size` bytes beginning at `start`.
+    pub(crate) fn new(start: Cluster, size: u32) -> Chain {
+        Chain { start, size, extents: None }
+    }
+
+    /// Builds the extent list from the FAT of `fs` if it has not been built
+    /// yet.
+    fn load_extents(&mut self, fs: &VFat) -> io::Result<()> {
+        if self.extents.is_none() {
+            let extents = if self.start.id() == 0 {
+                Extents::default()
+            } else {
+                Extents::from_chain(&fs.chain(self.start)?)
+            };
+            self.extents = Some(extents);
+        }
+        Ok(())
+    }
+
+    /// Allocates a cluster for position `index` of the chain, which must be
+    /// just past its end, and links it to the end of the chain.
+    fn extend(&mut self, fs: &mut VFat, index: usize) -> io::Result<Cluster> {
+        let len = self.extents.as_ref().map(|extents| extents.len()).unwrap_or(0);
+        if index != len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "file chain ends before end of file"));
+        }
+
+        let last = match len {
+            0 => None,
+            _ => self.extents.as_ref().and_then(|extents| extents.find(len - 1)).map(|(last, _)| last),
+        };
+        let cluster = fs.alloc_cluster(last)?;
+        if len == 0 {
+            self.start = cluster;
+        }
+        if let Some(ref mut extents) = self.extents {
+            extents.push(cluster);
+        }
+        Ok(cluster)
+    }
+}
 
 #[derive(Debug)]
 pub struct File {
-    start_cluster: Cluster,
     fs: Shared<VFat>,
     pub name: String,
     pub metadata: Metadata,
-    size: u32,
+    /// Borrowed immutably for reads and mutably for writes. When both are
+    /// held, `chain` is always borrowed before `fs`.
+    chain: Shared<Chain>,
     pos: usize,
-    curr: Cluster,
+    direct_io: bool,
+    /// The file's id in the file system's table of open files, if it has a
+    /// directory entry.
+    id: Option<u64>,
 }
 
 impl File {
-    pub fn new(fs: Shared<VFat>, start_cluster: Cluster, name: String, metadata: Metadata, size: u32) -> Self {
+    pub fn new(
+        fs: Shared<VFat>,
+        start_cluster: Cluster,
+        name: String,
+        metadata: Metadata,
+        size: u32,
+        dirent: Option<DirentLoc>
+    ) -> Self {
+        // handles to the same file share its chain
+        let (id, chain) = match dirent {
+            Some(loc) => {
+                let (id, chain) = fs.borrow_mut().open_file(loc, start_cluster, size);
+                (Some(id), chain)
+            }
+            None => (None, Shared::new(Chain::new(start_cluster, size))),
+        };
         File {
             fs,
-            start_cluster,
             name,
             metadata,
-            size,
+            chain,
             pos: 0,
-            curr: start_cluster
+            direct_io: false,
+            id
+        }
+    }
+
+    /// The location of the file's directory entry, which follows the entry
+    /// when it is renamed. `None` if the file has no entry or if the entry
+    /// has been removed.
+    pub(crate) fn dirent(&self) -> Option<DirentLoc> {
+        self.id.and_then(|id| self.fs.borrow().open_file_loc(id))
+    }
+
+    /// The first cluster of the file's data. Zero for an empty file.
+    pub(crate) fn start_cluster(&self) -> Cluster {
+        self.chain.borrow().start
+    }
+
+    /// Enables or disables direct I/O for reads of this file.
+    ///
+    /// With direct I/O enabled, reads of whole clusters that begin on a
+    /// cluster boundary are made straight from the device into the caller's
+    /// buffer without passing through, or being retained in, the sector cache.
+    /// Partial cluster reads, writes, and metadata accesses remain cached.
+    pub fn set_direct_io(&mut self, direct_io: bool) {
+        self.direct_io = direct_io;
+    }
+
+    /// Returns `true` if direct I/O is enabled for reads of this file.
+    pub fn direct_io(&self) -> bool {
+        self.direct_io
+    }
+
+    /// Reads bytes starting at `offset` in the file into `buf`, returning the
+    /// number of bytes read.
+    ///
+    /// Unlike `io::Read::read`, the file's position is neither used nor
+    /// changed, so a single `File` may be read from several threads at once.
+    /// Reading at or beyond the end of the file returns `Ok(0)`.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_from(min(offset, ::std::u32::MAX as u64) as usize, buf)
+    }
+
+    /// Writes the bytes in `buf` starting at `offset` in the file, returning
+    /// the number of bytes written. Writing past the end of the file extends
+    /// it.
+    ///
+    /// Unlike `io::Write::write`, the file's position is neither used nor
+    /// changed. The file's directory entry is updated, but `metadata` is not.
+    ///
+    /// # Errors
+    ///
+    /// Writing at an `offset` beyond the end of the file results in an
+    /// `InvalidInput` error.
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> io::Result<usize> {
+        self.write_to(min(offset, ::std::u32::MAX as u64) as usize, buf, Timestamp::now())
+    }
+
+    /// Builds the file's extent list if it has not been built yet.
+    fn load_extents(&self) -> io::Result<()> {
+        if self.chain.borrow().extents.is_none() {
+            let mut chain = self.chain.borrow_mut();
+            let fs = self.fs.borrow();
+            chain.load_extents(&fs)?;
+        }
+        Ok(())
+    }
+
+    /// Reads bytes starting at `offset` into `buf`, returning the number of
+    /// bytes read.
+    fn read_from(&self, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+        self.load_extents()?;
+        let chain = self.chain.borrow();
+        let fs = self.fs.borrow();
+        self.current_dirent(&fs)?;
+        let size = chain.size as usize;
+        let cluster_bytes = fs.cluster_size();
+        let extents = match chain.extents {
+            Some(ref extents) => extents,
+            None => return Ok(0),
+        };
+
+        let mut pos = offset;
+        let mut read = 0;
+        while read < buf.len() && pos < size {
+            // read as much of the run of contiguous clusters as possible
+            let (cluster, run) = match extents.find(pos / cluster_bytes) {
+                Some(found) => found,
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, "file chain ends before end of file")),
+            };
+            let cluster_offset = pos % cluster_bytes;
+            let run_bytes_remaining = run * cluster_bytes - cluster_offset;
+            let max_read = min(min(run_bytes_remaining, buf.len() - read), size - pos);
+            let bytes_read = if self.direct_io && cluster_offset == 0 && max_read >= cluster_bytes {
+                let whole_clusters = max_read - max_read % cluster_bytes;
+                fs.read_clusters_direct(cluster, &mut buf[read..(read + whole_clusters)])?
+            } else {
+                fs.read_clusters(cluster, cluster_offset, &mut buf[read..(read + max_read)])?
+            };
+            read += bytes_read;
+            pos += bytes_read;
+        }
+        Ok(read)
+    }
+
+    /// Writes `buf` starting at `offset`, extending the file's chain as
+    /// necessary, and records the new size and the modification time
+    /// `modified` in the file's directory entry. Returns the number of bytes
+    /// written.
+    ///
+    /// If an error, such as a full disk, occurs after some bytes were written,
+    /// those bytes are recorded and their count is returned; the error is
+    /// only returned if nothing was written.
+    fn write_to(&self, offset: usize, buf: &[u8], modified: Timestamp) -> io::Result<usize> {
+        let max_size = ::std::u32::MAX as usize;
+        if buf.len() == 0 {
+            return Ok(0);
+        } else if offset >= max_size {
+            return Err(io::Error::new(io::ErrorKind::Other, "file too large"));
+        }
+
+        let buf = &buf[..min(buf.len(), max_size - offset)];
+        let mut guard = self.chain.borrow_mut();
+        let chain = &mut *guard;
+        if offset > chain.size as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "write beyond end of file"));
+        }
+
+        let mut fs = self.fs.borrow_mut();
+        let loc = self.current_dirent(&fs)?;
+        chain.load_extents(&fs)?;
+        let cluster_bytes = fs.cluster_size();
+        let mut pos = offset;
+        let mut written = 0;
+        let mut error = None;
+        while written < buf.len() {
+            let index = pos / cluster_bytes;
+            let found = chain.extents.as_ref().and_then(|extents| extents.find(index));
+            let cluster = match found {
+                Some((cluster, _)) => cluster,
+                // past the end of the chain; extend it by a cluster
+                None => match chain.extend(&mut fs, index) {
+                    Ok(cluster) => cluster,
+                    Err(e) => { error = Some(e); break },
+                },
+            };
+
+            let cluster_offset = pos % cluster_bytes;
+            let max_write = min(cluster_bytes - cluster_offset, buf.len() - written);
+            let bytes_written = match fs.write_cluster(cluster, cluster_offset, &buf[written..(written + max_write)]) {
+                Ok(bytes_written) => bytes_written,
+                Err(e) => { error = Some(e); break },
+            };
+            written += bytes_written;
+            pos += bytes_written;
+            if pos > chain.size as usize {
+                chain.size = pos as u32;
+            }
+        }
+
+        // the chain may have grown even if nothing was written
+        store_dirent(&mut fs, loc, chain, modified)?;
+        match error {
+            Some(e) if written == 0 => Err(e),
+            _ => Ok(written),
+        }
+    }
+
+    /// Returns the current location of the file's directory entry, as
+    /// tracked by `fs`'s table of open files, or `None` if the file has no
+    /// entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `NotFound` if the file was removed through
+    /// another handle. Its clusters and slot may since have been reused.
+    fn current_dirent(&self, fs: &VFat) -> io::Result<Option<DirentLoc>> {
+        match self.id {
+            Some(id) => match fs.open_file_loc(id) {
+                Some(loc) => Ok(Some(loc)),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "file was removed")),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Records `modified` as the file's modification and access time.
+    fn touch(&mut self, modified: Timestamp) {
+        self.metadata.modified = modified;
+        self.metadata.accessed = modified.date;
+    }
+}
+
+/// Writes the start cluster and size in `chain` and the modification time
+/// `modified` to the directory entry at `loc`, if any.
+fn store_dirent(fs: &mut VFat, loc: Option<DirentLoc>, chain: &Chain, modified: Timestamp) -> io::Result<()> {
+    if let Some(loc) = loc {
+        {
+            let dirent = fs.dirent_mut(loc)?;
+            dirent.set_start_cluster(chain.start);
+            dirent.set_size(chain.size);
+            dirent.set_modified(modified);
+        }
+        fs.write_pending()?;
+    }
+    Ok(())
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.fs.borrow_mut().close_file(id);
         }
     }
 }
@@ -33,63 +317,87 @@ impl File {
 
 impl traits::File for File {
     fn sync(&mut self) -> io::Result<()> {
-        unimplemented!("File::sync")
+        self.fs.borrow_mut().sync()
     }
 
     fn size(&self) -> u64 {
-        self.size as u64
+        self.chain.borrow().size as u64
     }
-}
 
-impl io::Read for File {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut read = 0;
-        let cluster_bytes = {
-            let fs = self.fs.borrow();
-            fs.bytes_per_sector as usize * fs.sectors_per_cluster as usize
-        };
-        if self.size == 0 {
-            return Ok(0);
+    fn set_len(&mut self, size: u64) -> io::Result<()> {
+        if size > ::std::u32::MAX as u64 {
+            return Err(io::Error::new(io::ErrorKind::Other, "file too large"));
         }
-        loop {
-            if buf.len() - read == 0 {
-                // caller buffer is full
-                break;
-            }
-            let cluster_offset = self.pos % cluster_bytes;
-            let cluster_bytes_remaining = cluster_bytes - cluster_offset;
-            let max_read = min(min(cluster_bytes_remaining, buf.len() - read), self.size as usize - self.pos as usize);
-            let mut fs = self.fs.borrow_mut();
-            let bytes_read = fs.read_cluster(self.curr, cluster_offset, &mut buf[read..(read + max_read)])?;
-            read += bytes_read;
-            self.pos += bytes_read;
-            if self.pos == self.size as usize {
-                // end of file
-                break;
+
+        let pos = min(self.pos as u64, size);
+        let len = self.size();
+        if size > len {
+            // extending: write zeroes at the end, allocating as necessary
+            let zeroes = vec![0; self.fs.borrow().cluster_size()];
+            let mut remaining = (size - len) as usize;
+            self.seek(SeekFrom::End(0))?;
+            while remaining > 0 {
+                let len = min(remaining, zeroes.len());
+                self.write_all(&zeroes[..len])?;
+                remaining -= len;
             }
-            if bytes_read == cluster_bytes_remaining {
-                // advance to next cluster
-                let entry = fs.fat_entry(self.curr).unwrap();
-                match entry.status() {
-                    Status::Data(cluster) => self.curr = cluster, 
-                    Status::Eoc(_) => panic!("read past end of chain"),
-                    Status::Reserved => panic!("read of reserved cluster"),
-                    Status::Free => panic!("read of free cluster"),
-                    Status::Bad => panic!("file contains bad sector(s)"),
+        } else if size < len {
+            let now = Timestamp::now();
+            {
+                let mut guard = self.chain.borrow_mut();
+                let chain = &mut *guard;
+                let mut fs = self.fs.borrow_mut();
+                let loc = self.current_dirent(&fs)?;
+                let cluster_bytes = fs.cluster_size() as u64;
+                let keep = ((size + cluster_bytes - 1) / cluster_bytes) as usize;
+                if keep == 0 {
+                    fs.free_chain(chain.start)?;
+                    chain.start = Cluster::from(0);
+                } else {
+                    let last = match fs.chain(chain.start)?.get(keep - 1) {
+                        Some(&last) => last,
+                        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "file chain ends before end of file")),
+                    };
+                    let next = fs.fat_entry(last)?.status();
+                    fs.set_fat_entry(last, Status::Eoc(0xFFFFFFF))?;
+                    if let Status::Data(next) = next {
+                        fs.free_chain(next)?;
+                    }
                 }
+                chain.size = size as u32;
+                chain.extents = None;
+                store_dirent(&mut fs, loc, chain, now)?;
             }
+            self.touch(now);
         }
+
+        self.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+}
+
+impl io::Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.read_from(self.pos, buf)?;
+        self.pos += read;
         Ok(read)
     }
 }
 
 impl io::Write for File {
-    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!()
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = Timestamp::now();
+        let written = self.write_to(self.pos, buf, now)?;
+        if written > 0 {
+            self.pos += written;
+            self.touch(now);
+        }
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        unimplemented!()
+        use traits::File;
+        self.sync()
     }
 }
 
@@ -107,7 +415,23 @@ impl io::Seek for File {
     ///
     /// Seeking before the start of a file or beyond the end of the file results
     /// in an `InvalidInput` error.
-    fn seek(&mut self, _pos: SeekFrom) -> io::Result<u64> {
-        unimplemented!("File::seek()")
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        use traits::File;
+
+        let size = self.size() as i64;
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => size + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+
+        if new_pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"));
+        } else if new_pos > size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek beyond end of file"));
+        }
+
+        self.pos = new_pos as usize;
+        Ok(self.pos as u64)
     }
 }
\ No newline at end of file
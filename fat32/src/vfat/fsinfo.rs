@@ -0,0 +1,39 @@
+use std::fmt;
+
+/// The FAT32 file system information (FSInfo) sector.
+///
+/// The FSInfo sector caches the number of free clusters and a hint for where
+/// to begin searching for a free cluster. Both values are advisory: either may
+/// be unknown (`0xFFFFFFFF`) or stale.
+#[repr(C, packed)]
+pub struct FsInfo {
+    lead_signature: u32,
+    _reserved: [u8; 480],
+    struct_signature: u32,
+    pub free_count: u32,
+    pub next_free: u32,
+    _reserved2: [u8; 12],
+    trail_signature: u32,
+}
+
+impl FsInfo {
+    /// The value of `free_count` or `next_free` when the value is unknown.
+    pub const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    /// Returns `true` if all three FSInfo signatures are valid.
+    pub fn is_valid(&self) -> bool {
+        self.lead_signature == 0x41615252
+            && self.struct_signature == 0x61417272
+            && self.trail_signature == 0xAA550000
+    }
+}
+
+impl fmt::Debug for FsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FsInfo")
+            .field("free_count", &{ self.free_count })
+            .field("next_free", &{ self.next_free })
+            .field("valid", &self.is_valid())
+            .finish()
+    }
+}
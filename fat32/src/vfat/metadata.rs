@@ -1,4 +1,5 @@
 use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use traits;
 
@@ -33,6 +34,41 @@ pub struct Metadata {
     pub modified: Timestamp
 }
 
+impl Timestamp {
+    /// Returns the current (UTC) time as a FAT timestamp. Times outside of the
+    /// range representable by FAT, 1980 through 2107, are clamped.
+    pub fn now() -> Timestamp {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        // Convert days since the epoch to a civil date. See
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+        let days = (secs / 86400) as i64 + 719468;
+        let era = days / 146097;
+        let doe = days - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u16;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u16;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        if year < 1980 {
+            return Timestamp { date: Date((1 << 5) | 1), time: Time(0) };
+        } else if year > 2107 {
+            return Timestamp { date: Date((127 << 9) | (12 << 5) | 31), time: Time(0xBF7D) };
+        }
+
+        let rem = secs % 86400;
+        let (hour, minute, second) = (rem / 3600, (rem / 60) % 60, rem % 60);
+        Timestamp {
+            date: Date((((year - 1980) as u16) << 9) | (month << 5) | day),
+            time: Time(((hour as u16) << 11) | ((minute as u16) << 5) | ((second / 2) as u16)),
+        }
+    }
+}
+
 impl traits::Timestamp for Timestamp {
     fn year(&self) -> usize {
         (1980 + (self.date.0 >> 9)) as usize
@@ -2,12 +2,17 @@ pub(crate) mod file;
 pub(crate) mod dir;
 pub(crate) mod vfat;
 pub(crate) mod ebpb;
+pub(crate) mod fsinfo;
 pub(crate) mod error;
 pub(crate) mod cluster;
 pub(crate) mod fat;
 pub(crate) mod entry;
 pub(crate) mod metadata;
 pub(crate) mod cache;
+pub(crate) mod options;
+pub(crate) mod extent;
+pub(crate) mod stats;
+pub(crate) mod bitmap;
 pub(crate) mod shared;
 
 pub use self::ebpb::BiosParameterBlock;
@@ -18,7 +23,14 @@ pub use self::vfat::VFat;
 pub use self::entry::Entry;
 pub use self::metadata::{Metadata, Attributes, Date, Time, Timestamp};
 pub use self::shared::Shared;
+pub use self::options::{MountOptions, WritePolicy};
+pub use self::stats::Stats;
+pub use self::fat::FatType;
 
 pub(crate) use self::cache::{CachedDevice, Partition};
+pub(crate) use self::fsinfo::FsInfo;
 pub(crate) use self::fat::{Status, FatEntry};
 pub(crate) use self::cluster::Cluster;
+pub(crate) use self::dir::DirentLoc;
+pub(crate) use self::extent::Extents;
+pub(crate) use self::bitmap::ClusterBitmap;
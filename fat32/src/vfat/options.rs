@@ -0,0 +1,50 @@
+/// Options that control how a `VFat` file system is mounted.
+///
+/// `MountOptions::default()` yields the options used by `VFat::from()`.
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    /// The maximum number of logical sectors kept in the sector cache. When
+    /// the cache is full, the least recently used sector is evicted, and
+    /// written back first if it is dirty. `None`, the default, places no bound
+    /// on the cache.
+    pub cache_capacity: Option<usize>,
+    /// The write policy for file system metadata: FAT, FSInfo, and directory
+    /// sectors. Defaults to `WritePolicy::WriteBack`.
+    pub metadata_policy: WritePolicy,
+    /// The write policy for file contents. Defaults to
+    /// `WritePolicy::WriteBack`.
+    pub data_policy: WritePolicy,
+    /// Whether to build an in-memory bitmap of free clusters when mounting.
+    /// The bitmap is built with one pass over the FAT and lets clusters be
+    /// allocated and counted without reading the FAT again. Defaults to
+    /// `false`.
+    pub free_bitmap: bool,
+    /// Whether to index directories by name. A directory's index maps the
+    /// lowercase name of each entry to its location; it is built the first
+    /// time an entry is looked up in the directory and discarded when the
+    /// directory is modified. Defaults to `false`.
+    pub dir_index: bool,
+}
+
+/// When modifications to cached sectors reach the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Modified sectors are written to the device when they are evicted from
+    /// the cache or when the file system is synced.
+    WriteBack,
+    /// Modified sectors are written to the device before the operation that
+    /// modified them returns.
+    WriteThrough,
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions {
+            cache_capacity: None,
+            metadata_policy: WritePolicy::WriteBack,
+            data_policy: WritePolicy::WriteBack,
+            free_bitmap: false,
+            dir_index: false,
+        }
+    }
+}
@@ -4,14 +4,16 @@ use std::ops::{Deref, DerefMut};
 ///
 /// The inner `T` can be borrowed immutably with `.borrow()` and mutably with
 /// `.borrow_mut()`. The implementation guarantees the usual reference
-/// guarantees.
+/// guarantees: any number of immutable borrows may be held at once, from any
+/// number of threads, while a mutable borrow is exclusive. On ROS, every
+/// borrow is exclusive.
 #[derive(Debug)]
 pub struct Shared<T>(imp::Inner<T>);
 
 #[cfg(target_os = "ros")]
 mod imp {
     use std::rc::Rc;
-    use std::sync::Mutex;
+    use std::sync::{Mutex, MutexGuard};
     use super::Shared;
 
     pub type Inner<T> = Rc<Mutex<T>>;
@@ -20,6 +22,14 @@ mod imp {
         Rc::new(Mutex::new(val))
     }
 
+    pub fn borrow<T>(inner: &Inner<T>) -> MutexGuard<T> {
+        inner.lock().expect("all okay")
+    }
+
+    pub fn borrow_mut<T>(inner: &Inner<T>) -> MutexGuard<T> {
+        inner.lock().expect("all okay")
+    }
+
     // Without an enabled MMU/cache, the processor faults on atomic accesses.
     // As such, use an `Rc` instead of an `Arc` when running on ROS until
     // multithreading, the MMU, and caches are enabled.
@@ -29,12 +39,20 @@ mod imp {
 
 #[cfg(not(target_os = "ros"))]
 mod imp {
-    use std::sync::{Arc, Mutex};
+    use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-    pub type Inner<T> = ::std::sync::Arc<::std::sync::Mutex<T>>;
+    pub type Inner<T> = ::std::sync::Arc<::std::sync::RwLock<T>>;
 
     pub fn new<T>(val: T) -> Inner<T> {
-        Arc::new(Mutex::new(val))
+        Arc::new(RwLock::new(val))
+    }
+
+    pub fn borrow<T>(inner: &Inner<T>) -> RwLockReadGuard<T> {
+        inner.read().expect("all okay")
+    }
+
+    pub fn borrow_mut<T>(inner: &Inner<T>) -> RwLockWriteGuard<T> {
+        inner.write().expect("all okay")
     }
 }
 
@@ -49,7 +67,7 @@ impl<T> Shared<T> {
     /// If the inner value is presently mutably borrowed, this function blocks
     /// until that borrow is returned.
     pub fn borrow<'a>(&'a self) -> impl Deref<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        imp::borrow(&self.0)
     }
 
     /// Returns an mutable borrow to the inner value.
@@ -57,7 +75,7 @@ impl<T> Shared<T> {
     /// If the inner value is presently borrowed, mutably or immutably, this
     /// function blocks until all borrows are returned.
     pub fn borrow_mut<'a>(&'a self) -> impl DerefMut<Target = T> + 'a {
-        self.0.lock().expect("all okay")
+        imp::borrow_mut(&self.0)
     }
 }
 
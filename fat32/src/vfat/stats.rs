@@ -0,0 +1,22 @@
+/// A snapshot of the cache and I/O counters of a `VFat` file system.
+///
+/// Counters accumulate from when the file system is mounted or from the last
+/// call to `VFat::reset_stats()`, whichever is later.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Sector requests satisfied by the sector cache.
+    pub cache_hits: u64,
+    /// Sectors that were not cached and were read from the device into the
+    /// cache.
+    pub cache_misses: u64,
+    /// Sectors evicted from a full cache.
+    pub evictions: u64,
+    /// Dirty sectors written back to the device, on eviction or flush.
+    pub write_backs: u64,
+    /// Physical sectors read from the device, including direct reads.
+    pub sectors_read: u64,
+    /// Physical sectors written to the device.
+    pub sectors_written: u64,
+    /// FAT entries looked up.
+    pub fat_lookups: u64,
+}
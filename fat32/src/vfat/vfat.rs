@@ -1,27 +1,80 @@
-use std::io::Write;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io;
 use std::mem::size_of;
 use std::ops::Range;
 use std::path::{Path, Component};
 
 use mbr::MasterBootRecord;
-use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, Error, Status};
+use gpt::{GuidPartitionTable, Guid, PartitionId};
+use vfat::{Shared, Cluster, File, Dir, Entry, FatEntry, FatType, Error, Status, DirentLoc};
+use vfat::dir::{VFatDirEntry, VFatRegularDirEntry};
 use vfat::{Metadata, Attributes, Date, Time, Timestamp};
-use vfat::{BiosParameterBlock, CachedDevice, Partition};
+use vfat::{BiosParameterBlock, CachedDevice, Partition, FsInfo, MountOptions, WritePolicy, Stats};
+use vfat::ClusterBitmap;
+use vfat::file::Chain;
 use traits::{FileSystem, BlockDevice};
 
+/// A mounted FAT file system.
+///
+/// Modified sectors are cached and written back to the device when the file
+/// system is dropped: once the last `Shared<VFat>`, and every `File`, `Dir`,
+/// and `Entry` referring to it, is gone. An error during that final write-back
+/// cannot be reported, so call `sync()` beforehand to observe it.
 #[derive(Debug)]
 pub struct VFat {
-    device: CachedDevice,
+    /// The sector cache. It is locked separately so that reads can be made
+    /// through `&self`.
+    device: Mutex<CachedDevice>,
     pub bytes_per_sector: u16,
     pub sectors_per_cluster: u8,
     sectors_per_fat: u32,
+    fat_count: u8,
+    active_fat: Option<u8>,
+    fat_type: FatType,
     fat_start_sector: u64,
+    /// The fixed root directory region of a FAT12 or FAT16 volume. Empty for
+    /// a FAT32 volume.
+    root_dir_sectors: Range<u64>,
     data_start_sector: u64,
     data_sectors: u64,
     root_dir_cluster: Cluster,
+    fsinfo_sector: Option<u64>,
+    free_count: Option<u32>,
+    next_free: u32,
+    fat_lookups: AtomicUsize,
+    metadata_policy: WritePolicy,
+    data_policy: WritePolicy,
+    /// Sectors modified under a write-through policy that have not been
+    /// written to the device yet.
+    pending: Vec<u64>,
+    bitmap: Option<ClusterBitmap>,
+    /// Maps the first cluster of each indexed directory to its index.
+    dir_indexes: Option<HashMap<Cluster, HashMap<String, DirentLoc>>>,
+    /// The files with at least one open `File` handle, by id.
+    open_files: HashMap<u64, OpenFile>,
+    /// Maps the location of each open file's directory entry to its id.
+    open_locs: HashMap<(Cluster, usize), u64>,
+    next_open_id: u64,
 }
 
+/// The state of an open file, shared by all of its `File` handles so that a
+/// change made through one handle is seen by the others.
+#[derive(Debug)]
+struct OpenFile {
+    /// The location of the file's directory entry, or `None` once the entry
+    /// has been removed.
+    loc: Option<DirentLoc>,
+    chain: Shared<Chain>,
+    /// The number of `File` handles to the file.
+    handles: usize,
+}
+
+/// The MBR partition type of a GPT protective partition.
+const GPT_PROTECTIVE_TYPE: u8 = 0xEE;
+
 const ROOT_NAME: &str = "";
 const ROOT_MD: Metadata = Metadata { 
     attribs: Attributes(0),
@@ -31,41 +84,444 @@ const ROOT_MD: Metadata = Metadata {
 };
 
 impl VFat {
-    pub fn from<T>(mut device: T) -> Result<Shared<VFat>, Error>
+    /// Mounts the first FAT partition on `device`: the first MBR partition
+    /// whose type is a FAT type (see `PartitionEntry::is_fat()`) or, if there
+    /// is none, the first such logical partition in an extended partition. If
+    /// sector 0 of `device` is itself a FAT boot sector, the device has no
+    /// partition table and the volume spanning the whole device is mounted
+    /// instead.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no such partition.
+    pub fn from<T>(device: T) -> Result<Shared<VFat>, Error>
         where T: BlockDevice + 'static
     {
+        VFat::from_with_options(device, MountOptions::default())
+    }
+
+    /// Mounts the file system on `device` like `from()`, configured by
+    /// `options`.
+    ///
+    /// If the first MBR partition is a GPT protective partition, the first
+    /// GPT partition of type `Guid::BASIC_DATA` or `Guid::EFI_SYSTEM` is
+    /// mounted instead.
+    pub fn from_with_options<T>(
+        mut device: T,
+        options: MountOptions
+    ) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        match BiosParameterBlock::from(&mut device, 0) {
+            Ok(ref bpb) if bpb.is_fat() => return VFat::mount(device, 0, options),
+            _ => (),
+        }
+
         let mbr = MasterBootRecord::from(&mut device)?;
-        let part_start = {
-            let boot_part_ent = mbr.part_entries.iter().nth(0);
-            match boot_part_ent {
+        let first = &mbr.part_entries[0];
+        let part_start = if first.partition_type == GPT_PROTECTIVE_TYPE {
+            let gpt = GuidPartitionTable::from(&mut device)?;
+            let fat_entry = gpt.entries.iter().find(|entry| {
+                entry.type_guid == Guid::BASIC_DATA || entry.type_guid == Guid::EFI_SYSTEM
+            });
+            match fat_entry {
+                Some(entry) => entry.first_lba,
+                None => return Err(Error::NotFound),
+            }
+        } else {
+            match mbr.part_entries.iter().find(|entry| entry.is_fat()) {
                 Some(entry) => entry.start_sector as u64,
+                None => {
+                    let logical = mbr.logical_partitions(&mut device)?;
+                    match logical.iter().find(|entry| entry.is_fat()) {
+                        Some(entry) => entry.start_sector as u64,
+                        None => return Err(Error::NotFound),
+                    }
+                }
+            }
+        };
+
+        VFat::mount(device, part_start, options)
+    }
+
+    /// Mounts the file system in MBR partition `index` (0-indexed) on
+    /// `device`, regardless of the partition's type. Indices 0 through 3 are
+    /// the primary partitions; 4 onward are the logical partitions in the
+    /// order returned by `MasterBootRecord::logical_partitions()`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if there is no partition table entry `index`.
+    /// Returns `EmptyPartition(index)` if the entry is empty.
+    pub fn from_partition<T>(device: T, index: usize) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        VFat::from_partition_with_options(device, index, MountOptions::default())
+    }
+
+    /// Mounts the file system in MBR partition `index` on `device` like
+    /// `from_partition()`, configured by `options`.
+    pub fn from_partition_with_options<T>(
+        mut device: T,
+        index: usize,
+        options: MountOptions
+    ) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let mbr = MasterBootRecord::from(&mut device)?;
+        let entry = if index < mbr.part_entries.len() {
+            mbr.part_entries[index].clone()
+        } else {
+            let logical = mbr.logical_partitions(&mut device)?;
+            match logical.into_iter().nth(index - mbr.part_entries.len()) {
+                Some(entry) => entry,
                 None => return Err(Error::NotFound),
             }
         };
 
+        if entry.is_empty() {
+            return Err(Error::EmptyPartition(index));
+        }
+        let part_start = entry.start_sector as u64;
+
+        VFat::mount(device, part_start, options)
+    }
+
+    /// Mounts the file system in partition `id` of the GUID partition table
+    /// (GPT) on `device`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `NotFound` if the GPT has no used entry identified by `id`.
+    pub fn from_gpt<T>(device: T, id: PartitionId) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        VFat::from_gpt_with_options(device, id, MountOptions::default())
+    }
+
+    /// Mounts the file system in partition `id` of the GUID partition table
+    /// on `device` like `from_gpt()`, configured by `options`.
+    pub fn from_gpt_with_options<T>(
+        mut device: T,
+        id: PartitionId,
+        options: MountOptions
+    ) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
+        let gpt = GuidPartitionTable::from(&mut device)?;
+        let part_start = match gpt.find(id) {
+            Some(entry) => entry.first_lba,
+            None => return Err(Error::NotFound),
+        };
+
+        VFat::mount(device, part_start, options)
+    }
+
+    /// Mounts the file system whose boot sector is sector `part_start` of
+    /// `device`.
+    fn mount<T>(
+        mut device: T,
+        part_start: u64,
+        options: MountOptions
+    ) -> Result<Shared<VFat>, Error>
+        where T: BlockDevice + 'static
+    {
         let bpb = BiosParameterBlock::from(&mut device, part_start)?;
+
+        // the derived geometry below divides by these, and the sector cache
+        // requires whole device sectors per logical sector, so check them first
+        let invalid_geometry = || Error::Io(io::Error::new(io::ErrorKind::InvalidData, "invalid volume geometry"));
+        let device_sector_size = device.sector_size();
+        let sector_bytes = bpb.sector_bytes as u64;
+        if bpb.sectors_per_cluster == 0 || sector_bytes < device_sector_size
+            || sector_bytes % device_sector_size != 0 {
+            return Err(invalid_geometry());
+        }
+        let data_start_sector = bpb.data_start_sector();
+        let logical_sectors = bpb.total_sectors();
+        if logical_sectors < data_start_sector {
+            return Err(invalid_geometry());
+        }
+
         let part = Partition { start: part_start, sector_size: bpb.sector_bytes as u64 };
-        let part_device = CachedDevice::new(device, part);
-        let data_start_sector = (bpb.reserved_sectors as u64) 
-                + ((bpb.sectors_per_fat as u64) * (bpb.fat_count as u64));
+        let part_device = CachedDevice::with_capacity(device, part, options.cache_capacity);
+        let fat_type = bpb.fat_type();
 
-        let logical_sectors = if bpb.logical_sectors_small != 0 {
-            bpb.logical_sectors_small as u64
-        } else {
-            bpb.logical_sectors_large as u64
+        // FAT12 and FAT16 volumes have no flags, root directory cluster, or
+        // FSInfo sector; their root directory is the fixed region preceding
+        // the data region, addressed as cluster 0
+        let (flags, root_dir_cluster, fsinfo_sector) = match fat_type {
+            FatType::Fat32 => (bpb.flags, bpb.root_start_cluster, bpb.fsinfo_sector),
+            FatType::Fat12 | FatType::Fat16 => (0, 0, 0),
         };
 
+        // bit 7 of the flags disables mirroring; bits 0-3 select the only
+        // active FAT when it is disabled
+        let active_fat = if flags & 0x80 != 0 {
+            let active = (flags & 0x0F) as u8;
+            if active >= bpb.fat_count {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, "active FAT out of range")));
+            }
+            Some(active)
+        } else {
+            None
+        };
 
-        Ok(Shared::new(VFat { 
-            device: part_device,
+        let mut vfat = VFat {
+            device: Mutex::new(part_device),
             bytes_per_sector: bpb.sector_bytes as u16,
             sectors_per_cluster: bpb.sectors_per_cluster as u8,
-            sectors_per_fat: bpb.sectors_per_fat as u32,
+            sectors_per_fat: bpb.fat_sectors(),
+            fat_count: bpb.fat_count,
+            active_fat: active_fat,
+            fat_type: fat_type,
             fat_start_sector: bpb.reserved_sectors as u64,
+            root_dir_sectors: data_start_sector - bpb.root_dir_sectors()..data_start_sector,
             data_start_sector: data_start_sector,
-            root_dir_cluster: Cluster::from(bpb.root_start_cluster),
+            root_dir_cluster: Cluster::from(root_dir_cluster),
             data_sectors: logical_sectors - data_start_sector,
-        }))
+            fsinfo_sector: None,
+            free_count: None,
+            next_free: 2,
+            fat_lookups: AtomicUsize::new(0),
+            metadata_policy: options.metadata_policy,
+            data_policy: options.data_policy,
+            pending: Vec::new(),
+            bitmap: None,
+            dir_indexes: if options.dir_index { Some(HashMap::new()) } else { None },
+            open_files: HashMap::new(),
+            open_locs: HashMap::new(),
+            next_open_id: 0,
+        };
+        vfat.load_fsinfo(fsinfo_sector as u64)?;
+        if options.free_bitmap {
+            let bitmap = vfat.build_bitmap()?;
+            vfat.free_count = Some(bitmap.free_count());
+            vfat.bitmap = Some(bitmap);
+        }
+
+        Ok(Shared::new(vfat))
+    }
+
+    /// Reads the free cluster count and next free cluster hint from the FSInfo
+    /// sector `sector`. An invalid FSInfo sector or invalid values within it
+    /// are ignored.
+    fn load_fsinfo(&mut self, sector: u64) -> io::Result<()> {
+        if sector == 0 || sector >= self.fat_start_sector {
+            return Ok(());
+        }
+
+        let (free_count, next_free) = {
+            let fsinfo = unsafe { &*(self.device_mut().get(sector)?.as_ptr() as *const FsInfo) };
+            if !fsinfo.is_valid() {
+                return Ok(());
+            }
+            (fsinfo.free_count, fsinfo.next_free)
+        };
+
+        let cluster_count = self.cluster_count();
+        self.fsinfo_sector = Some(sector);
+        if free_count <= cluster_count {
+            self.free_count = Some(free_count);
+        }
+        if next_free >= 2 && next_free < cluster_count + 2 {
+            self.next_free = next_free;
+        }
+        Ok(())
+    }
+
+    /// Writes the in-memory free cluster count and next free cluster hint to
+    /// the FSInfo sector, if there is one.
+    fn store_fsinfo(&mut self) -> io::Result<()> {
+        if let Some(sector) = self.fsinfo_sector {
+            let free_count = self.free_count.unwrap_or(FsInfo::UNKNOWN);
+            let next_free = self.next_free;
+            {
+                let fsinfo = unsafe { &mut *(self.device_mut().get_mut(sector)?.as_mut_ptr() as *mut FsInfo) };
+                fsinfo.free_count = free_count;
+                fsinfo.next_free = next_free;
+            }
+            let policy = self.metadata_policy;
+            self.mark_modified(sector, policy);
+        }
+        self.write_pending()
+    }
+
+    /// Returns the number of free clusters in the file system. If the count is
+    /// not known from the FSInfo sector, it is computed by scanning the FAT.
+    pub fn free_clusters(&mut self) -> io::Result<u32> {
+        if let Some(free_count) = self.free_count {
+            return Ok(free_count);
+        }
+
+        let free_count = self.build_bitmap()?.free_count();
+        self.free_count = Some(free_count);
+        self.store_fsinfo()?;
+        Ok(free_count)
+    }
+
+    /// Builds a bitmap of the free clusters in the file system with a single
+    /// sequential pass over the active FAT.
+    fn build_bitmap(&self) -> io::Result<ClusterBitmap> {
+        let cluster_count = self.cluster_count();
+        let mut bitmap = ClusterBitmap::new(cluster_count);
+
+        let bytes_per_sector = self.bytes_per_sector as u64;
+        let entry_bytes = self.fat_type.entry_bytes() as u64;
+        let fat_bytes = self.fat_type.entry_offset(cluster_count + 2) + entry_bytes;
+        let fat_sectors = min((fat_bytes + bytes_per_sector - 1) / bytes_per_sector, self.sectors_per_fat as u64);
+        let fat_start = self.fat_start_sector
+            + (self.active_fat.unwrap_or(0) as u64) * (self.sectors_per_fat as u64);
+
+        // a multiple of three sectors, so that no FAT12 entry spans two chunks
+        const CHUNK_SECTORS: u64 = 48;
+        let mut buf = vec![0; (CHUNK_SECTORS * bytes_per_sector) as usize];
+        let mut sector = 0;
+        let mut id = 2;
+        while sector < fat_sectors {
+            let count = min(CHUNK_SECTORS, fat_sectors - sector);
+            self.device().read_direct(fat_start + sector, count, &mut buf)?;
+            let (chunk_start, chunk_end) = (sector * bytes_per_sector, (sector + count) * bytes_per_sector);
+            while id < cluster_count + 2 {
+                let offset = self.fat_type.entry_offset(id);
+                if offset + entry_bytes > chunk_end {
+                    break;
+                }
+
+                let value = self.fat_type.decode(id, &buf[((offset - chunk_start) as usize)..]);
+                if FatEntry::new(self.fat_type, value).status() != Status::Free {
+                    bitmap.set_used(Cluster::from(id), true);
+                }
+                id += 1;
+            }
+            sector += count;
+        }
+        Ok(bitmap)
+    }
+
+    /// Returns `n` free clusters, preferring a run of `n` contiguous clusters.
+    /// The clusters are not allocated. The search begins at the next free
+    /// cluster hint. If the free cluster bitmap is enabled, the FAT is not
+    /// read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if fewer than `n` clusters are free.
+    pub fn find_free_clusters(&self, n: u32) -> io::Result<Vec<Cluster>> {
+        let hint = Cluster::from(self.next_free);
+        let found = match self.bitmap {
+            Some(ref bitmap) => bitmap.find_free_run(n, hint),
+            None => self.scan_free_run(n, hint)?,
+        };
+        found.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "not enough free clusters"))
+    }
+
+    /// Searches the FAT for `n` free clusters like
+    /// `ClusterBitmap::find_free_run()`, beginning at `hint` and wrapping
+    /// around to the start of the FAT. The scan stops at the first run of `n`
+    /// contiguous free clusters; runs do not wrap around.
+    fn scan_free_run(&self, n: u32, hint: Cluster) -> io::Result<Option<Vec<Cluster>>> {
+        if n == 0 {
+            return Ok(Some(vec![]));
+        }
+
+        let cluster_count = self.cluster_count();
+        let start = hint.id().saturating_sub(2) % cluster_count.max(1);
+        let mut first = Vec::with_capacity(n as usize);
+        let (mut run_start, mut run_len) = (0, 0);
+        for i in 0..cluster_count {
+            let index = (start + i) % cluster_count;
+            if index == 0 {
+                run_len = 0;
+            }
+
+            let id = index + 2;
+            if self.fat_entry(Cluster::from(id))?.status() != Status::Free {
+                run_len = 0;
+                continue;
+            }
+            if run_len == 0 {
+                run_start = id;
+            }
+            run_len += 1;
+            if run_len == n {
+                return Ok(Some((run_start..(id + 1)).map(Cluster::from).collect()));
+            }
+            if first.len() < n as usize {
+                first.push(Cluster::from(id));
+            }
+        }
+
+        Ok(if first.len() == n as usize { Some(first) } else { None })
+    }
+
+    /// Writes all modified sectors, including FAT, directory, and FSInfo
+    /// sectors, back to the underlying device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the device fails. This is the only way
+    /// to observe write-back errors; those that occur when the file system is
+    /// dropped are lost.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.pending.clear();
+        self.device_mut().flush()
+    }
+
+    /// Records that `sector` was modified. If `policy` is write-through, the
+    /// sector is written by the next call to `write_pending()`.
+    fn mark_modified(&mut self, sector: u64, policy: WritePolicy) {
+        if policy == WritePolicy::WriteThrough {
+            self.pending.push(sector);
+        }
+    }
+
+    /// Writes every sector modified under a write-through policy to the
+    /// device. Operations that hand out references into cached sectors, such
+    /// as `dir_slot_mut()`, rely on their callers to call this once the
+    /// modification is complete.
+    pub(crate) fn write_pending(&mut self) -> io::Result<()> {
+        self.pending.sort();
+        self.pending.dedup();
+        while let Some(&sector) = self.pending.last() {
+            self.device_mut().write_back(sector)?;
+            self.pending.pop();
+        }
+        Ok(())
+    }
+
+    /// Locks and returns the sector cache for an access through `&self`.
+    fn device(&self) -> MutexGuard<CachedDevice> {
+        self.device.lock().expect("all okay")
+    }
+
+    /// Returns the sector cache for an access through `&mut self`, which needs
+    /// no locking.
+    fn device_mut(&mut self) -> &mut CachedDevice {
+        self.device.get_mut().expect("all okay")
+    }
+
+    /// Returns a snapshot of the file system's cache and I/O counters.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.device().stats();
+        stats.fat_lookups = self.fat_lookups.load(Ordering::Relaxed) as u64;
+        stats
+    }
+
+    /// Resets every counter returned by `stats()` to zero.
+    pub fn reset_stats(&mut self) {
+        self.device_mut().reset_stats();
+        self.fat_lookups.store(0, Ordering::Relaxed);
+    }
+
+    /// The size of a cluster in bytes.
+    pub fn cluster_size(&self) -> usize {
+        self.bytes_per_sector as usize * self.sectors_per_cluster as usize
+    }
+
+    /// The number of data clusters in the file system.
+    fn cluster_count(&self) -> u32 {
+        (self.data_sectors / self.sectors_per_cluster as u64) as u32
     }
 
     fn coords(&self, cluster: Cluster, offset: usize) -> (Range<u64>, usize) {
@@ -78,30 +534,95 @@ impl VFat {
 
 
     pub fn read_cluster(
-        &mut self,
+        &self,
         cluster: Cluster,
         offset: usize,
-        mut buf: &mut [u8]
+        buf: &mut [u8]
     ) -> io::Result<usize> {
         assert!(offset < (self.sectors_per_cluster as usize) * (self.bytes_per_sector as usize),
                 "read offset exceeds cluster size");
 
+        let len = min(buf.len(), self.cluster_size() - offset);
+        self.read_clusters(cluster, offset, &mut buf[..len])
+    }
+
+    /// Reads `buf.len()` bytes beginning at byte `offset` of cluster `start`,
+    /// continuing into the clusters that immediately follow `start` on disk.
+    /// Sectors that are not cached are read from the device in as few requests
+    /// as possible. Returns the number of bytes read.
+    ///
+    /// The caller must ensure that every cluster read belongs to the same
+    /// chain, in order.
+    pub fn read_clusters(
+        &self,
+        start: Cluster,
+        offset: usize,
+        buf: &mut [u8]
+    ) -> io::Result<usize> {
+        let (sectors, sector_offset) = { self.coords(start, offset) };
+        self.device().read(sectors.start, sector_offset, buf)
+    }
+
+    /// Reads the clusters beginning at `start` and immediately following it on
+    /// disk into `buf`, whose length must be a multiple of the cluster size.
+    /// The data is read straight from the device, bypassing the sector cache.
+    /// Returns the number of bytes read.
+    ///
+    /// The caller must ensure that every cluster read belongs to the same
+    /// chain, in order.
+    pub fn read_clusters_direct(&self, start: Cluster, buf: &mut [u8]) -> io::Result<usize> {
+        assert!(buf.len() % self.cluster_size() == 0, "direct read is not whole clusters");
+
+        let (sectors, _) = { self.coords(start, 0) };
+        let count = (buf.len() / self.bytes_per_sector as usize) as u64;
+        self.device().read_direct(sectors.start, count, buf)
+    }
+
+    /// Writes `buf` into cluster `cluster` starting at byte `offset` in the
+    /// cluster. Returns the number of bytes written, which is less than
+    /// `buf.len()` only if `buf` extends past the end of the cluster.
+    pub fn write_cluster(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8]
+    ) -> io::Result<usize> {
+        let policy = self.data_policy;
+        self.write_cluster_with(cluster, offset, buf, policy)
+    }
+
+    fn write_cluster_with(
+        &mut self,
+        cluster: Cluster,
+        offset: usize,
+        buf: &[u8],
+        policy: WritePolicy
+    ) -> io::Result<usize> {
+        assert!(offset < self.cluster_size(), "write offset exceeds cluster size");
+
         let (sectors, start_offset) = { self.coords(cluster, offset) };
-        let mut bytes_read = 0;
+        let mut bytes_written = 0;
         let start_sector = sectors.start;
         for sector in sectors {
-            let data = self.device.get(sector)?;
-            bytes_read += if sector != start_sector {
-                buf.write(&data)?
-            } else {
-                buf.write(&data[start_offset..])?
+            if bytes_written == buf.len() {
+                break;
             }
+            let len = {
+                let data = self.device_mut().get_mut(sector)?;
+                let start = if sector != start_sector { 0 } else { start_offset };
+                let len = min(data.len() - start, buf.len() - bytes_written);
+                data[start..(start + len)].copy_from_slice(&buf[bytes_written..(bytes_written + len)]);
+                len
+            };
+            self.mark_modified(sector, policy);
+            bytes_written += len;
         }
-        Ok(bytes_read)
+        self.write_pending()?;
+        Ok(bytes_written)
     }
 
     pub fn read_chain(
-        &mut self,
+        &self,
         start: Cluster,
         buf: &mut Vec<u8>
     ) -> io::Result<usize> {
@@ -117,7 +638,9 @@ impl VFat {
                 Status::Free => panic!("trying to read free cluster"),
                 Status::Bad => Err(io::Error::new(io::ErrorKind::InvalidData, "cluster contains bad sector(s)"))
             };
-            bytes_read += self.read_cluster(curr, 0, buf)?;
+            let start = buf.len();
+            buf.resize(start + self.cluster_size(), 0);
+            bytes_read += self.read_cluster(curr, 0, &mut buf[start..])?;
             match next {
                 Ok(Some(cluster)) => curr = cluster,
                 Ok(None) => break,
@@ -127,31 +650,437 @@ impl VFat {
         Ok(bytes_read)
     }
 
+    /// Returns the clusters in the chain beginning at `start`, in order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the chain contains a free,
+    /// reserved, or bad cluster.
+    pub fn chain(&self, start: Cluster) -> io::Result<Vec<Cluster>> {
+        let mut chain = vec![start];
+        loop {
+            let curr = chain[chain.len() - 1];
+            match self.fat_entry(curr)?.status() {
+                Status::Data(cluster) => chain.push(cluster),
+                Status::Eoc(_) => return Ok(chain),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid cluster in chain")),
+            }
+        }
+    }
 
-    pub fn fat_entry(&mut self, cluster: Cluster) -> io::Result<&FatEntry> {
+    /// Overwrites every byte in `cluster` with zero. Clusters are only zeroed
+    /// as they are added to directories, so the metadata write policy applies.
+    pub fn zero_cluster(&mut self, cluster: Cluster) -> io::Result<()> {
+        let zeroes = vec![0; self.cluster_size()];
+        let policy = self.metadata_policy;
+        self.write_cluster_with(cluster, 0, &zeroes, policy)?;
+        Ok(())
+    }
+
+    /// Returns the sector and offset within that sector of the entry for
+    /// `cluster` in copy `fat` of the FAT. A FAT12 entry may continue into the
+    /// following sector.
+    fn fat_entry_coords(&self, fat: u8, cluster: Cluster) -> (u64, usize) {
         assert!(cluster.data_offset() < self.data_sectors, "cluster out of bounds");
-        let cluster_fat_offset = cluster.id() * (size_of::<FatEntry>() as u32);
-        let entry_sector = self.fat_start_sector + (cluster_fat_offset as u64) / (self.bytes_per_sector as u64);
-        let entry_offset = (cluster_fat_offset % (self.bytes_per_sector as u32)) as usize;
-        let fat_entry = &mut (self.device.get_mut(entry_sector)?[entry_offset]) as *mut u8 as *mut FatEntry;
+        let cluster_fat_offset = self.fat_type.entry_offset(cluster.id());
+        let entry_sector = self.fat_start_sector
+            + (fat as u64) * (self.sectors_per_fat as u64)
+            + cluster_fat_offset / (self.bytes_per_sector as u64);
+        let entry_offset = (cluster_fat_offset % (self.bytes_per_sector as u64)) as usize;
+        (entry_sector, entry_offset)
+    }
+
+    /// Returns the entry for `cluster` in the active FAT.
+    pub fn fat_entry(&self, cluster: Cluster) -> io::Result<FatEntry> {
+        self.fat_lookups.fetch_add(1, Ordering::Relaxed);
+        let (entry_sector, entry_offset) = self.fat_entry_coords(self.active_fat.unwrap_or(0), cluster);
+        let mut bytes = [0; 4];
+        let len = self.fat_type.entry_bytes();
+        self.device().read(entry_sector, entry_offset, &mut bytes[..len])?;
+        Ok(FatEntry::new(self.fat_type, self.fat_type.decode(cluster.id(), &bytes)))
+    }
+
+    /// Sets the FAT entry for `cluster` to `status`. The upper four reserved
+    /// bits of a FAT32 entry are preserved.
+    ///
+    /// If FAT mirroring is enabled, every copy of the FAT is updated.
+    /// Otherwise, only the active FAT is updated.
+    pub fn set_fat_entry(&mut self, cluster: Cluster, status: Status) -> io::Result<()> {
+        let fats = match self.active_fat {
+            Some(active) => active..(active + 1),
+            None => 0..self.fat_count,
+        };
+
+        let len = self.fat_type.entry_bytes();
+        for fat in fats {
+            let (entry_sector, entry_offset) = self.fat_entry_coords(fat, cluster);
+            let mut bytes = [0; 4];
+            self.device_mut().read(entry_sector, entry_offset, &mut bytes[..len])?;
+            self.fat_type.encode(cluster.id(), &status, &mut bytes);
+            self.write_metadata(entry_sector, entry_offset, &bytes[..len])?;
+        }
+        if let Some(ref mut bitmap) = self.bitmap {
+            bitmap.set_used(cluster, status != Status::Free);
+        }
+        self.write_pending()
+    }
+
+    /// Copies `bytes` to byte `offset` of sector `sector`, continuing into the
+    /// sectors that follow it, and marks each sector modified under the
+    /// metadata write policy.
+    fn write_metadata(&mut self, sector: u64, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        let policy = self.metadata_policy;
+        let bytes_per_sector = self.bytes_per_sector as usize;
+        let (mut sector, mut offset, mut written) = (sector, offset, 0);
+        while written < bytes.len() {
+            let len = min(bytes_per_sector - offset, bytes.len() - written);
+            self.device_mut().get_mut(sector)?[offset..(offset + len)]
+                .copy_from_slice(&bytes[written..(written + len)]);
+            self.mark_modified(sector, policy);
+            written += len;
+            sector += 1;
+            offset = 0;
+        }
+        Ok(())
+    }
+
+    /// Returns every cluster in the chain beginning at `start` to the free
+    /// pool. Does nothing if `start` is cluster 0 (an empty file).
+    pub fn free_chain(&mut self, start: Cluster) -> io::Result<()> {
+        if start.id() == 0 {
+            return Ok(());
+        }
+        self.invalidate_dir_index(start);
+        for cluster in self.chain(start)? {
+            self.set_fat_entry(cluster, Status::Free)?;
+            self.free_count = self.free_count.map(|count| count + 1);
+        }
+        self.store_fsinfo()
+    }
+
+    /// Returns the first free cluster at or after the next free cluster hint,
+    /// wrapping around to the start of the FAT, or `None` if there is none.
+    fn find_free_cluster(&self) -> io::Result<Option<Cluster>> {
+        let hint = Cluster::from(self.next_free);
+        if let Some(ref bitmap) = self.bitmap {
+            return Ok(bitmap.find_free(hint));
+        }
+
+        let cluster_count = self.cluster_count();
+        for i in 0..cluster_count {
+            let cluster = Cluster::from(2 + (hint.id() - 2 + i) % cluster_count);
+            if self.fat_entry(cluster)?.status() == Status::Free {
+                return Ok(Some(cluster));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Allocates a free cluster and marks it as the end of its chain. If
+    /// `prev` is `Some`, the new cluster is linked to the end of `prev`.
+    ///
+    /// The search for a free cluster begins at the FSInfo next free cluster
+    /// hint and wraps around to the start of the FAT. If the free cluster
+    /// bitmap is enabled, the search does not read the FAT. The FSInfo sector
+    /// is updated to reflect the allocation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `Other` if there are no free clusters.
+    pub fn alloc_cluster(&mut self, prev: Option<Cluster>) -> io::Result<Cluster> {
+        let cluster_count = self.cluster_count();
+        if self.free_count == Some(0) {
+            return Err(io::Error::new(io::ErrorKind::Other, "file system is full"));
+        }
+
+        if let Some(cluster) = self.find_free_cluster()? {
+            let id = cluster.id();
+            self.set_fat_entry(cluster, Status::Eoc(0xFFFFFFF))?;
+            if let Some(prev) = prev {
+                self.set_fat_entry(prev, Status::Data(cluster))?;
+            }
+            self.next_free = if id + 1 < cluster_count + 2 { id + 1 } else { 2 };
+            self.free_count = self.free_count.map(|count| count.saturating_sub(1));
+            self.store_fsinfo()?;
+            return Ok(cluster);
+        }
+
+        self.free_count = Some(0);
+        self.store_fsinfo()?;
+        Err(io::Error::new(io::ErrorKind::Other, "file system is full"))
+    }
+
+    /// Returns the sector holding the 32-byte slot `index` of the directory
+    /// beginning at cluster `dir` and the slot's offset within that sector.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the directory's chain ends
+    /// before slot `index`.
+    fn dir_slot_coords(&self, dir: Cluster, index: usize) -> io::Result<(u64, usize)> {
+        let dirent_size = size_of::<VFatDirEntry>();
+        if self.is_fixed_root(dir) {
+            let bytes_per_sector = self.bytes_per_sector as usize;
+            let sector = self.root_dir_sectors.start + ((index * dirent_size) / bytes_per_sector) as u64;
+            if sector >= self.root_dir_sectors.end {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "directory entry past end of root directory"));
+            }
+            Ok((sector, (index * dirent_size) % bytes_per_sector))
+        } else {
+            let per_cluster = self.cluster_size() / dirent_size;
+            let mut cluster = dir;
+            for _ in 0..(index / per_cluster) {
+                cluster = match self.fat_entry(cluster)?.status() {
+                    Status::Data(next) => next,
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "directory entry past end of chain")),
+                };
+            }
+
+            let (sectors, offset) = self.coords(cluster, (index % per_cluster) * dirent_size);
+            Ok((sectors.start, offset))
+        }
+    }
+
+    /// Returns a mutable reference to the 32-byte slot `index` of the
+    /// directory beginning at cluster `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `InvalidData` if the directory's chain ends
+    /// before slot `index`.
+    pub(crate) fn dir_slot_mut(&mut self, dir: Cluster, index: usize) -> io::Result<&mut VFatDirEntry> {
+        let (sector, offset) = self.dir_slot_coords(dir, index)?;
+        let policy = self.metadata_policy;
+        self.mark_modified(sector, policy);
+        let slot = &mut self.device_mut().get_mut(sector)?[offset] as *mut u8 as *mut VFatDirEntry;
+        unsafe {
+            Ok(&mut *slot)
+        }
+    }
+
+    /// Returns `true` if `dir` designates the fixed root directory region of a
+    /// FAT12 or FAT16 volume rather than a cluster.
+    pub(crate) fn is_fixed_root(&self, dir: Cluster) -> bool {
+        dir.id() == 0 && self.fat_type != FatType::Fat32
+    }
+
+    /// The number of bytes of directory entries held by `cluster` of a
+    /// directory: the size of the fixed root directory region if `cluster`
+    /// designates it, the cluster size otherwise.
+    pub(crate) fn dir_cluster_size(&self, cluster: Cluster) -> usize {
+        if self.is_fixed_root(cluster) {
+            let sectors = self.root_dir_sectors.end - self.root_dir_sectors.start;
+            sectors as usize * self.bytes_per_sector as usize
+        } else {
+            self.cluster_size()
+        }
+    }
+
+    /// Reads `cluster` of a directory, or the fixed root directory region if
+    /// `cluster` designates it, into `buf`. Returns the number of bytes read.
+    pub(crate) fn read_dir_cluster(&self, cluster: Cluster, buf: &mut [u8]) -> io::Result<usize> {
+        if self.is_fixed_root(cluster) {
+            let len = min(buf.len(), self.dir_cluster_size(cluster));
+            self.device().read(self.root_dir_sectors.start, 0, &mut buf[..len])
+        } else {
+            self.read_cluster(cluster, 0, buf)
+        }
+    }
+
+    /// Appends every entry slot of the directory beginning at `dir` to `buf`.
+    /// Returns the number of bytes read.
+    pub(crate) fn read_dir(&self, dir: Cluster, buf: &mut Vec<u8>) -> io::Result<usize> {
+        if self.is_fixed_root(dir) {
+            let start = buf.len();
+            buf.resize(start + self.dir_cluster_size(dir), 0);
+            self.read_dir_cluster(dir, &mut buf[start..])
+        } else {
+            self.read_chain(dir, buf)
+        }
+    }
+
+    /// The FAT type of the file system.
+    pub fn fat_type(&self) -> FatType {
+        self.fat_type
+    }
+
+    /// Returns `true` if directories are indexed by name.
+    pub(crate) fn dir_index_enabled(&self) -> bool {
+        self.dir_indexes.is_some()
+    }
+
+    /// Looks up the lowercase name `name` in the index of the directory
+    /// beginning at `dir`. Returns `None` if the directory is not indexed.
+    pub(crate) fn dir_index_lookup(&self, dir: Cluster, name: &str) -> Option<Option<DirentLoc>> {
+        self.dir_indexes.as_ref()
+            .and_then(|indexes| indexes.get(&dir))
+            .map(|index| index.get(name).cloned())
+    }
+
+    /// Stores `index` as the index of the directory beginning at `dir`.
+    pub(crate) fn set_dir_index(&mut self, dir: Cluster, index: HashMap<String, DirentLoc>) {
+        if let Some(ref mut indexes) = self.dir_indexes {
+            indexes.insert(dir, index);
+        }
+    }
+
+    /// Discards the index of the directory beginning at `dir`, if any.
+    pub(crate) fn invalidate_dir_index(&mut self, dir: Cluster) {
+        if let Some(ref mut indexes) = self.dir_indexes {
+            indexes.remove(&dir);
+        }
+    }
 
+    /// Returns a mutable reference to the regular directory entry at `loc`.
+    pub(crate) fn dirent_mut(&mut self, loc: DirentLoc) -> io::Result<&mut VFatRegularDirEntry> {
+        let slot = self.dir_slot_mut(loc.dir, loc.index)?;
         unsafe {
-            Ok(&*fat_entry)
+            Ok(&mut slot.regular)
+        }
+    }
+
+    /// Registers a new handle to the file whose directory entry is at `loc`
+    /// and returns the file's id and chain. If the file is already open, the
+    /// chain of its other handles is returned and `start` and `size` are
+    /// ignored; otherwise a chain beginning at `start` of length `size` is
+    /// created.
+    pub(crate) fn open_file(&mut self, loc: DirentLoc, start: Cluster, size: u32) -> (u64, Shared<Chain>) {
+        let key = (loc.dir, loc.index);
+        if let Some(&id) = self.open_locs.get(&key) {
+            let file = self.open_files.get_mut(&id).expect("open file is registered");
+            file.handles += 1;
+            return (id, file.chain.clone());
+        }
+
+        let id = self.next_open_id;
+        self.next_open_id += 1;
+        let chain = Shared::new(Chain::new(start, size));
+        self.open_files.insert(id, OpenFile { loc: Some(loc), chain: chain.clone(), handles: 1 });
+        self.open_locs.insert(key, id);
+        (id, chain)
+    }
+
+    /// Unregisters a handle to the open file `id`, forgetting the file once
+    /// its last handle is gone.
+    pub(crate) fn close_file(&mut self, id: u64) {
+        let closed = match self.open_files.get_mut(&id) {
+            Some(file) => {
+                file.handles -= 1;
+                file.handles == 0
+            }
+            None => false,
+        };
+        if closed {
+            if let Some(loc) = self.open_files.remove(&id).unwrap().loc {
+                self.open_locs.remove(&(loc.dir, loc.index));
+            }
+        }
+    }
+
+    /// The location of the directory entry of the open file `id`, or `None`
+    /// if the entry has been removed.
+    pub(crate) fn open_file_loc(&self, id: u64) -> Option<DirentLoc> {
+        self.open_files.get(&id).and_then(|file| file.loc)
+    }
+
+    /// Records that the directory entry at `from`, if it belongs to an open
+    /// file, has moved to `to`.
+    pub(crate) fn move_file(&mut self, from: DirentLoc, to: DirentLoc) {
+        if let Some(id) = self.open_locs.remove(&(from.dir, from.index)) {
+            self.open_files.get_mut(&id).expect("open file is registered").loc = Some(to);
+            self.open_locs.insert((to.dir, to.index), id);
+        }
+    }
+
+    /// Records that the directory entry at `loc` has been removed. The open
+    /// handles to its file, if any, no longer refer to an entry, and a file
+    /// later created in the same slot does not share their state.
+    pub(crate) fn detach_file(&mut self, loc: DirentLoc) {
+        if let Some(id) = self.open_locs.remove(&(loc.dir, loc.index)) {
+            self.open_files.get_mut(&id).expect("open file is registered").loc = None;
         }
     }
 
+    /// The first cluster of the root directory.
+    pub(crate) fn root_cluster(&self) -> Cluster {
+        self.root_dir_cluster
+    }
+
     fn root(&self, aref: &Shared<VFat>) -> Dir {
-        Dir { fs: aref.clone(), start_cluster: self.root_dir_cluster, name: String::from(ROOT_NAME), metadata: ROOT_MD }
+        Dir { fs: aref.clone(), start_cluster: self.root_dir_cluster, name: String::from(ROOT_NAME), metadata: ROOT_MD, dirent: None }
+    }
+}
+
+/// Opens the parent directory of the absolute path `path` and returns it along
+/// with the last component of `path`.
+///
+/// # Errors
+///
+/// If `path` is not absolute, has no last component, or if its parent is not
+/// an existing directory, an error kind of `InvalidInput` is returned.
+fn open_parent<'p>(fs: &Shared<VFat>, path: &'p Path) -> io::Result<(Dir, &'p str)> {
+    if !path.is_absolute() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an absolute path"));
+    }
+
+    let name = match path.components().last() {
+        Some(Component::Normal(name)) => name.to_str()
+            .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "invalid utf-8 in name"))?,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "path has no file name")),
+    };
+
+    let parent = path.parent().unwrap();
+    match fs.open(parent) {
+        Ok(Entry::Dir(dir)) => Ok((dir, name)),
+        Ok(Entry::File(_)) => Err(io::Error::new(io::ErrorKind::InvalidInput, "parent is not a directory")),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "parent directory does not exist"))
+        }
+        Err(e) => Err(e),
     }
 }
 
+/// Returns `true` if `ancestor` is `dir` or one of `dir`'s ancestors.
+fn is_ancestor(ancestor: &Dir, dir: &Dir) -> io::Result<bool> {
+    if dir.start_cluster == ancestor.start_cluster {
+        return Ok(true);
+    }
+
+    match dir.find("..") {
+        Ok(Entry::Dir(parent)) => is_ancestor(ancestor, &parent),
+        Ok(Entry::File(_)) => Ok(false),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Removes `entry` from its parent directory `parent` and frees its clusters.
+/// If `entry` is a directory, its children are first removed depth-first.
+fn remove_entry(fs: &Shared<VFat>, parent: &Dir, entry: Entry) -> io::Result<()> {
+    use traits::{Dir, Entry};
+
+    if let Some(dir) = entry.as_dir() {
+        for child in dir.entries()? {
+            if child.name() != "." && child.name() != ".." {
+                remove_entry(fs, dir, child)?;
+            }
+        }
+    }
+
+    let loc = entry.dirent().expect("removed entry has a parent");
+    parent.remove(loc)?;
+    fs.borrow_mut().detach_file(loc);
+    let start = entry.start_cluster();
+    fs.borrow_mut().free_chain(start)
+}
+
 impl<'a> FileSystem for &'a Shared<VFat> {
     type File = File;
     type Dir = Dir;
     type Entry = Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        let mut cwd = self.borrow_mut().root(self);
+        let mut cwd = self.borrow().root(self);
         let mut iter = path.as_ref().components().peekable();
         if iter.next() != Some(Component::RootDir) {
             return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an absolute path"));
@@ -189,23 +1118,130 @@ impl<'a> FileSystem for &'a Shared<VFat> {
         return Ok(Entry::Dir(cwd));
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        if parent.find(name).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+        }
+
+        let now = Timestamp::now();
+        let template = VFatRegularDirEntry::new(Attributes(0x20), Cluster::from(0), now);
+        let loc = parent.insert(name, template)?;
+        let metadata = Metadata { attribs: Attributes(0x20), created: now, accessed: now.date, modified: now };
+        Ok(File::new(self.clone(), Cluster::from(0), name.to_string(), metadata, 0, Some(loc)))
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
         where P: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let path = path.as_ref();
+        if parents {
+            if let Some(parent) = path.parent() {
+                match self.open(parent) {
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound
+                        || e.kind() == io::ErrorKind::InvalidInput => {
+                        self.create_dir(parent, true)?;
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let (parent, name) = open_parent(self, path)?;
+        if parent.find(name).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "directory exists"));
+        }
+
+        let now = Timestamp::now();
+        let cluster = {
+            let mut fs = self.borrow_mut();
+            let cluster = fs.alloc_cluster(None)?;
+            fs.zero_cluster(cluster)?;
+
+            let parent_cluster = if parent.start_cluster == fs.root_dir_cluster {
+                Cluster::from(0)
+            } else {
+                parent.start_cluster
+            };
+
+            let mut dot = VFatRegularDirEntry::new(Attributes(0x10), cluster, now);
+            dot.set_short_name(b".          ");
+            *fs.dir_slot_mut(cluster, 0)? = VFatDirEntry { regular: dot };
+            let mut dotdot = VFatRegularDirEntry::new(Attributes(0x10), parent_cluster, now);
+            dotdot.set_short_name(b"..         ");
+            *fs.dir_slot_mut(cluster, 1)? = VFatDirEntry { regular: dotdot };
+            cluster
+        };
+
+        let template = VFatRegularDirEntry::new(Attributes(0x10), cluster, now);
+        let loc = match parent.insert(name, template) {
+            Ok(loc) => loc,
+            Err(e) => {
+                self.borrow_mut().free_chain(cluster)?;
+                return Err(e);
+            }
+        };
+
+        let metadata = Metadata { attribs: Attributes(0x10), created: now, accessed: now.date, modified: now };
+        Ok(Dir { fs: self.clone(), start_cluster: cluster, name: name.to_string(), metadata, dirent: Some(loc) })
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
         where P: AsRef<Path>, Q: AsRef<Path>
     {
-        unimplemented!("read only file system")
+        let (from_parent, from_name) = open_parent(self, from.as_ref())?;
+        let (to_parent, to_name) = open_parent(self, to.as_ref())?;
+        let entry = from_parent.find(from_name)?;
+        let loc = entry.dirent().expect("renamed entry has a parent");
+        match to_parent.find(to_name) {
+            // a rename that only changes the case of the name
+            Ok(ref existing) if existing.dirent() == Some(loc) => (),
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "destination exists")),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        let moved = from_parent.start_cluster != to_parent.start_cluster;
+        if let Entry::Dir(ref dir) = entry {
+            if moved && is_ancestor(dir, &to_parent)? {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot move a directory into itself"));
+            }
+        }
+
+        let slots = from_parent.remove(loc)?;
+        let template = unsafe { slots[slots.len() - 1].regular };
+        match to_parent.insert(to_name, template) {
+            Ok(new_loc) => self.borrow_mut().move_file(loc, new_loc),
+            Err(e) => {
+                from_parent.restore(loc, &slots)?;
+                return Err(e);
+            }
+        }
+
+        if let Entry::Dir(ref dir) = entry {
+            if moved {
+                let mut fs = self.borrow_mut();
+                let parent_cluster = if to_parent.start_cluster == fs.root_dir_cluster {
+                    Cluster::from(0)
+                } else {
+                    to_parent.start_cluster
+                };
+                let dotdot = DirentLoc { dir: dir.start_cluster, index: 1, lfn_count: 0 };
+                fs.dirent_mut(dotdot)?.set_start_cluster(parent_cluster);
+                fs.write_pending()?;
+            }
+        }
+        Ok(())
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        let (parent, name) = open_parent(self, path.as_ref())?;
+        let entry = parent.find(name)?;
+        if let Entry::Dir(ref dir) = entry {
+            if !children && !dir.is_empty()? {
+                return Err(io::Error::new(io::ErrorKind::Other, "directory is not empty"));
+            }
+        }
+        remove_entry(self, &parent, entry)
     }
 }